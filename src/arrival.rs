@@ -0,0 +1,114 @@
+use crate::config::ArrivalConfig;
+use crate::config::ArrivalDistribution;
+use rand::rngs::StdRng;
+use rand::Rng;
+use rand::SeedableRng;
+use std::error::Error;
+use std::time::Duration;
+
+// Random gaps between the starts of scenario chains
+pub struct Arrivals {
+    distribution: ArrivalDistribution,
+    jitter: f64,
+    rng: StdRng,
+}
+
+impl Arrivals {
+    pub fn new(config: &ArrivalConfig) -> Result<Arrivals, Box<dyn Error>> {
+        Self::with_rng(config, StdRng::from_entropy())
+    }
+
+    fn with_rng(config: &ArrivalConfig, rng: StdRng) -> Result<Arrivals, Box<dyn Error>> {
+        if !(0.0..=1.0).contains(&config.jitter) {
+            return Err("arrival jitter must be between 0.0 and 1.0".into());
+        }
+        Ok(Arrivals {
+            distribution: config.distribution,
+            jitter: config.jitter,
+            rng,
+        })
+    }
+
+    // Chains are started one by one rather than in batches on a fixed interval
+    pub fn is_randomized(&self) -> bool {
+        self.distribution != ArrivalDistribution::Constant
+    }
+
+    // Gap until the next start, averaging `rate` starts per second
+    pub fn next_gap(&mut self, rate: f64) -> Duration {
+        let mean = 1.0 / rate;
+        let gap = match self.distribution {
+            ArrivalDistribution::Constant => mean,
+            // Exponential inter-arrival times
+            ArrivalDistribution::Poisson => {
+                let u: f64 = self.rng.gen();
+                -(1.0 - u).ln() * mean
+            }
+            ArrivalDistribution::Uniform => {
+                mean * self.rng.gen_range(1.0 - self.jitter..=1.0 + self.jitter)
+            }
+        };
+        Duration::from_secs_f64(gap)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn arrivals(distribution: ArrivalDistribution, jitter: f64) -> Arrivals {
+        let config = ArrivalConfig {
+            distribution,
+            jitter,
+        };
+        Arrivals::with_rng(&config, StdRng::seed_from_u64(7)).unwrap()
+    }
+
+    fn mean_gap(arrivals: &mut Arrivals, rate: f64, samples: u32) -> f64 {
+        let total: f64 = (0..samples)
+            .map(|_| arrivals.next_gap(rate).as_secs_f64())
+            .sum();
+        total / samples as f64
+    }
+
+    #[test]
+    fn test_arrivals_poisson() {
+        let mut arrivals = arrivals(ArrivalDistribution::Poisson, 0.5);
+        assert!(arrivals.is_randomized());
+        let mean = mean_gap(&mut arrivals, 1000.0, 100_000);
+        assert!((mean - 0.001).abs() < 0.00002, "mean gap {}", mean);
+
+        // Exponential, about e^-1 of the gaps are longer than the mean
+        let longer = (0..100_000)
+            .filter(|_| arrivals.next_gap(1000.0) > Duration::from_millis(1))
+            .count();
+        assert!((longer as f64 / 100_000.0 - (-1.0f64).exp()).abs() < 0.01);
+    }
+
+    #[test]
+    fn test_arrivals_uniform() {
+        let mut arrivals = arrivals(ArrivalDistribution::Uniform, 0.2);
+        for _ in 0..10_000 {
+            let gap = arrivals.next_gap(100.0).as_secs_f64();
+            assert!((0.008 - 1e-9..=0.012 + 1e-9).contains(&gap), "gap {}", gap);
+        }
+        let mean = mean_gap(&mut arrivals, 100.0, 100_000);
+        assert!((mean - 0.01).abs() < 0.0001, "mean gap {}", mean);
+    }
+
+    #[test]
+    fn test_arrivals_constant() {
+        let mut arrivals = arrivals(ArrivalDistribution::Constant, 0.5);
+        assert!(!arrivals.is_randomized());
+        assert_eq!(arrivals.next_gap(4.0), Duration::from_millis(250));
+    }
+
+    #[test]
+    fn test_arrivals_invalid_jitter() {
+        let config = ArrivalConfig {
+            distribution: ArrivalDistribution::Uniform,
+            jitter: 1.5,
+        };
+        assert!(Arrivals::new(&config).is_err());
+    }
+}
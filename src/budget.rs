@@ -0,0 +1,145 @@
+use crate::config::AssertConfig;
+use std::error::Error;
+use std::sync::atomic::AtomicU32;
+use std::sync::atomic::Ordering;
+use std::sync::OnceLock;
+
+// The error rate of the first responses says little, a single failure would
+// be 100%
+const MIN_RESPONSES: u32 = 100;
+
+// Failed requests of all runners, the run is aborted once it is exceeded
+pub struct ErrorBudget {
+    max_failures: Option<u32>,
+    max_error_rate: Option<f64>,
+    responses: AtomicU32,
+    failures: AtomicU32,
+    exceeded: OnceLock<String>,
+}
+
+impl ErrorBudget {
+    pub fn new(config: &AssertConfig) -> Result<ErrorBudget, Box<dyn Error>> {
+        if config.max_failures == Some(0) {
+            return Err("assert max_failures must be at least 1".into());
+        }
+        if let Some(rate) = config.max_error_rate {
+            if !(0.0..=100.0).contains(&rate) {
+                return Err("assert max_error_rate must be between 0 and 100".into());
+            }
+        }
+        Ok(ErrorBudget {
+            max_failures: config.max_failures,
+            max_error_rate: config.max_error_rate,
+            responses: AtomicU32::new(0),
+            failures: AtomicU32::new(0),
+            exceeded: OnceLock::new(),
+        })
+    }
+
+    // Counts a completed request, returns the reason when it is the one that
+    // exceeded the budget
+    pub fn record(&self, failed: bool) -> Option<&str> {
+        let responses = self.responses.fetch_add(1, Ordering::Relaxed) + 1;
+        let failures = if failed {
+            self.failures.fetch_add(1, Ordering::Relaxed) + 1
+        } else {
+            self.failures.load(Ordering::Relaxed)
+        };
+        let error_rate = failures as f64 / responses as f64 * 100.0;
+
+        let reason = match (self.max_failures, self.max_error_rate) {
+            (Some(max), _) if failures >= max => {
+                format!("{} failed requests, max_failures is {}", failures, max)
+            }
+            (_, Some(max)) if responses >= MIN_RESPONSES && error_rate > max => format!(
+                "error rate {:.2}% ({}/{}), max_error_rate is {:.2}%",
+                error_rate, failures, responses, max
+            ),
+            _ => return None,
+        };
+        match self.exceeded.set(reason) {
+            Ok(_) => self.exceeded(),
+            Err(_) => None,
+        }
+    }
+
+    pub fn exceeded(&self) -> Option<&str> {
+        self.exceeded.get().map(|reason| reason.as_str())
+    }
+
+    pub fn is_exceeded(&self) -> bool {
+        self.exceeded.get().is_some()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::AssertFailure;
+
+    fn budget(max_failures: Option<u32>, max_error_rate: Option<f64>) -> ErrorBudget {
+        ErrorBudget::new(&AssertConfig {
+            on_failure: AssertFailure::Continue,
+            max_failures,
+            max_error_rate,
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn test_error_budget_max_failures() {
+        let budget = budget(Some(3), None);
+        assert!(budget.record(true).is_none());
+        assert!(budget.record(false).is_none());
+        assert!(budget.record(true).is_none());
+        assert!(!budget.is_exceeded());
+
+        assert_eq!(
+            budget.record(true),
+            Some("3 failed requests, max_failures is 3")
+        );
+        assert!(budget.is_exceeded());
+        // Reported once
+        assert!(budget.record(true).is_none());
+        assert_eq!(
+            budget.exceeded(),
+            Some("3 failed requests, max_failures is 3")
+        );
+    }
+
+    #[test]
+    fn test_error_budget_max_error_rate() {
+        let budget = budget(None, Some(5.0));
+        // A failing first response is not enough
+        assert!(budget.record(true).is_none());
+        for _ in 0..98 {
+            assert!(budget.record(false).is_none());
+        }
+        // 2 out of 100
+        assert!(budget.record(true).is_none());
+        for _ in 0..4 {
+            budget.record(true);
+        }
+        assert!(budget.is_exceeded());
+        assert_eq!(
+            budget.exceeded(),
+            Some("error rate 5.77% (6/104), max_error_rate is 5.00%")
+        );
+    }
+
+    #[test]
+    fn test_error_budget_unlimited() {
+        let budget = budget(None, None);
+        for _ in 0..1000 {
+            assert!(budget.record(true).is_none());
+        }
+        assert!(!budget.is_exceeded());
+
+        let config = AssertConfig {
+            on_failure: AssertFailure::Continue,
+            max_failures: None,
+            max_error_rate: Some(120.0),
+        };
+        assert!(ErrorBudget::new(&config).is_err());
+    }
+}
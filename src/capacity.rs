@@ -0,0 +1,158 @@
+use crate::config::AutoThrottleConfig;
+use std::error::Error;
+use std::time::Duration;
+
+// Outcome of running one step of the search
+pub struct StepResult {
+    pub p99: Duration,
+    pub error_rate: f64,
+}
+
+// Raises the rate by `step_rps` while the server keeps up. Once a rate is
+// unstable, bisects between the highest stable and the lowest unstable rate
+// until they are at most `min_step_rps` apart.
+pub struct CapacitySearch {
+    config: AutoThrottleConfig,
+    min_step_rps: u32,
+    current_rps: u32,
+    highest_stable: Option<u32>,
+    lowest_unstable: Option<u32>,
+}
+
+impl CapacitySearch {
+    pub fn new(config: &AutoThrottleConfig) -> Result<CapacitySearch, Box<dyn Error>> {
+        if config.start_rps == 0 || config.step_rps == 0 {
+            return Err("auto_throttle start_rps and step_rps must be greater than 0".into());
+        }
+        if config.step_duration.is_zero() {
+            return Err("auto_throttle step_duration must be greater than 0".into());
+        }
+        let min_step_rps = config.min_step_rps.unwrap_or(config.step_rps / 8).max(1);
+
+        Ok(CapacitySearch {
+            config: config.clone(),
+            min_step_rps,
+            current_rps: config.start_rps.min(config.max_rps.unwrap_or(u32::MAX)),
+            highest_stable: None,
+            lowest_unstable: None,
+        })
+    }
+
+    pub fn current_rps(&self) -> u32 {
+        self.current_rps
+    }
+
+    pub fn step_duration(&self) -> Duration {
+        self.config.step_duration
+    }
+
+    pub fn highest_stable(&self) -> Option<u32> {
+        self.highest_stable
+    }
+
+    pub fn is_stable(&self, result: &StepResult) -> bool {
+        result.p99 <= self.config.max_p99 && result.error_rate <= self.config.max_error_rate
+    }
+
+    // Rate of the next step, None once the search is done
+    pub fn next(&mut self, result: &StepResult) -> Option<u32> {
+        let max_rps = self.config.max_rps.unwrap_or(u32::MAX);
+
+        if self.is_stable(result) {
+            self.highest_stable = Some(self.current_rps);
+        } else {
+            self.lowest_unstable = Some(self.current_rps);
+        }
+
+        let stable = self.highest_stable.unwrap_or(0);
+        self.current_rps = match self.lowest_unstable {
+            Some(unstable) => {
+                if unstable.saturating_sub(stable) <= self.min_step_rps {
+                    return None;
+                }
+                stable + (unstable - stable) / 2
+            }
+            None => {
+                if stable >= max_rps {
+                    return None;
+                }
+                stable.saturating_add(self.config.step_rps).min(max_rps)
+            }
+        };
+
+        Some(self.current_rps)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config() -> AutoThrottleConfig {
+        AutoThrottleConfig {
+            start_rps: 1000,
+            step_rps: 1000,
+            max_rps: None,
+            min_step_rps: Some(250),
+            step_duration: Duration::from_secs(10),
+            max_p99: Duration::from_millis(50),
+            max_error_rate: 1.0,
+        }
+    }
+
+    // Server keeping up until `capacity` RPS
+    fn run(search: &mut CapacitySearch, capacity: u32) -> Vec<u32> {
+        let mut rates = vec![search.current_rps()];
+        loop {
+            let p99 = if search.current_rps() <= capacity {
+                Duration::from_millis(10)
+            } else {
+                Duration::from_millis(200)
+            };
+            let result = StepResult {
+                p99,
+                error_rate: 0.0,
+            };
+            match search.next(&result) {
+                Some(rps) => rates.push(rps),
+                None => return rates,
+            }
+        }
+    }
+
+    #[test]
+    fn test_capacity_search() {
+        let mut search = CapacitySearch::new(&config()).unwrap();
+        let rates = run(&mut search, 3300);
+        assert_eq!(rates, vec![1000, 2000, 3000, 4000, 3500, 3250]);
+        assert_eq!(search.highest_stable(), Some(3250));
+    }
+
+    #[test]
+    fn test_capacity_search_max_rps() {
+        let mut config = config();
+        config.max_rps = Some(2500);
+        let mut search = CapacitySearch::new(&config).unwrap();
+        let rates = run(&mut search, 10000);
+        assert_eq!(rates, vec![1000, 2000, 2500]);
+        assert_eq!(search.highest_stable(), Some(2500));
+    }
+
+    #[test]
+    fn test_capacity_search_below_start() {
+        let mut search = CapacitySearch::new(&config()).unwrap();
+        let rates = run(&mut search, 600);
+        assert_eq!(rates, vec![1000, 500, 750]);
+        assert_eq!(search.highest_stable(), Some(500));
+    }
+
+    #[test]
+    fn test_capacity_search_error_rate() {
+        let search = CapacitySearch::new(&config()).unwrap();
+        let result = StepResult {
+            p99: Duration::from_millis(1),
+            error_rate: 1.5,
+        };
+        assert!(!search.is_stable(&result));
+    }
+}
@@ -1,9 +1,9 @@
 use crate::function;
 use crate::scenario;
 use crate::variable;
+use rand::Rng;
 use serde::Deserialize;
 use serde::Serialize;
-use serde_yaml;
 use std::collections::HashMap;
 use std::error::Error;
 use std::fmt;
@@ -15,6 +15,8 @@ use std::time::Duration;
 pub struct Config {
     pub log_level: LogLevel,
     pub parallel: u8,
+    pub interval_report: Option<IntervalReportConfig>,
+    pub metrics: Option<MetricsConfig>,
     pub runner: RunnerConfig,
 }
 
@@ -35,9 +37,9 @@ pub enum LogLevel {
     Trace,
 }
 
-impl Into<log::LevelFilter> for LogLevel {
-    fn into(self) -> log::LevelFilter {
-        match self {
+impl From<LogLevel> for log::LevelFilter {
+    fn from(level: LogLevel) -> Self {
+        match level {
             LogLevel::Off => log::LevelFilter::Off,
             LogLevel::Error => log::LevelFilter::Error,
             LogLevel::Warn => log::LevelFilter::Warn,
@@ -48,18 +50,92 @@ impl Into<log::LevelFilter> for LogLevel {
     }
 }
 
+// Progress of the run every `interval`, optionally written as CSV to `file`
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct IntervalReportConfig {
+    #[serde(deserialize_with = "humantime_duration_deserializer")]
+    pub interval: Duration,
+    pub file: Option<String>,
+}
+
+// Prometheus endpoint, `listen` is the address of the HTTP listener
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct MetricsConfig {
+    pub listen: String,
+}
+
 #[derive(Debug, Deserialize, Serialize, Clone)]
 pub struct RunnerConfig {
+    #[serde(default)]
+    pub mode: RunMode,
     pub target_rps: u32,
     #[serde(deserialize_with = "humantime_duration_deserializer")]
     pub duration: Duration,
     pub batch_size: BatchSize,
-    // pub auto_throttle: bool,
+    pub arrival: Option<ArrivalConfig>,
+    pub load_profile: Option<Vec<LoadStage>>,
+    pub auto_throttle: Option<AutoThrottleConfig>,
+    pub concurrency: Option<ConcurrencyConfig>,
     pub base_url: String,
+    #[serde(default = "default_connections_per_runner")]
+    pub connections_per_runner: u16,
+    pub max_in_flight: Option<u32>,
+    #[serde(default)]
+    pub backpressure: Backpressure,
+    #[serde(default)]
+    pub assert: AssertConfig,
+    pub tls: Option<TlsConfig>,
+    #[serde(default)]
+    pub reconnect: ReconnectConfig,
+    #[serde(default)]
+    pub http2: Http2Config,
+    pub ping: Option<PingConfig>,
     pub global: Global,
-    // #[serde(deserialize_with = "humantime_duration_deserializer")]
-    // pub delay_between_scenario: Duration,
+    #[serde(default)]
     pub scenarios: Vec<Scenario>,
+    pub flows: Option<Vec<Flow>>,
+}
+
+impl RunnerConfig {
+    // A plain `scenarios` list is a single flow
+    pub fn flows(&self) -> Vec<Flow> {
+        match &self.flows {
+            Some(flows) => flows.clone(),
+            None => vec![Flow {
+                name: "default".into(),
+                weight: 1,
+                scenarios: self.scenarios.clone(),
+            }],
+        }
+    }
+
+    // Names the stats of each scenario are kept under, in flow order and
+    // prefixed by the flow's name when flows are used
+    pub fn scenario_names(&self) -> Vec<String> {
+        match &self.flows {
+            Some(flows) => flows
+                .iter()
+                .flat_map(|flow| {
+                    flow.scenarios
+                        .iter()
+                        .map(move |scenario| format!("{}/{}", flow.name, scenario.name))
+                })
+                .collect(),
+            None => self.scenarios.iter().map(|s| s.name.clone()).collect(),
+        }
+    }
+}
+
+// Independent chain of scenarios, gets `weight` parts of the target rate
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct Flow {
+    pub name: String,
+    pub weight: u32,
+    pub scenarios: Vec<Scenario>,
+}
+
+fn default_connections_per_runner() -> u16 {
+    1
 }
 
 #[derive(Debug, Deserialize, Serialize, PartialEq, Clone)]
@@ -69,6 +145,160 @@ pub enum BatchSize {
     Fixed(u32),
 }
 
+// `rate` starts scenario chains at a target rate (open loop), `concurrency`
+// keeps a fixed number of virtual users looping through the chain (closed loop)
+#[derive(Debug, Deserialize, Serialize, PartialEq, Default, Copy, Clone)]
+#[serde(rename_all = "lowercase")]
+pub enum RunMode {
+    #[default]
+    Rate,
+    Concurrency,
+}
+
+// How the starts of scenario chains are spread in time. `constant` sends
+// batches on a fixed interval, `poisson` and `uniform` start chains one by one
+// with random gaps averaging the target rate, `uniform` within +/- `jitter` of it
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct ArrivalConfig {
+    pub distribution: ArrivalDistribution,
+    #[serde(default = "default_arrival_jitter")]
+    pub jitter: f64,
+}
+
+#[derive(Debug, Deserialize, Serialize, PartialEq, Copy, Clone)]
+#[serde(rename_all = "lowercase")]
+pub enum ArrivalDistribution {
+    Constant,
+    Poisson,
+    Uniform,
+}
+
+fn default_arrival_jitter() -> f64 {
+    0.5
+}
+
+// What to do with a send once `max_in_flight` requests are waiting for a
+// response, wait for one to complete or drop the rest of the chain
+#[derive(Debug, Deserialize, Serialize, PartialEq, Default, Copy, Clone)]
+#[serde(rename_all = "lowercase")]
+pub enum Backpressure {
+    #[default]
+    Wait,
+    Drop,
+}
+
+// Failed assertions are logged and counted, `abort_chain` also ends the chain
+// unless a `next` rule matches. The run is aborted once the failed requests of
+// all runners reach `max_failures` or their share `max_error_rate` (percent)
+#[derive(Debug, Deserialize, Serialize, Default, Clone)]
+pub struct AssertConfig {
+    #[serde(default)]
+    pub on_failure: AssertFailure,
+    pub max_failures: Option<u32>,
+    pub max_error_rate: Option<f64>,
+}
+
+#[derive(Debug, Deserialize, Serialize, PartialEq, Default, Copy, Clone)]
+#[serde(rename_all = "snake_case")]
+pub enum AssertFailure {
+    #[default]
+    Continue,
+    AbortChain,
+}
+
+// Virtual users of the concurrency mode, each waits `think_time` before
+// starting the chain again
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct ConcurrencyConfig {
+    pub users: u32,
+    #[serde(default, deserialize_with = "humantime_duration_deserializer")]
+    pub think_time: Duration,
+}
+
+// Runs at `target_rps` for `duration`, or ramps linearly to it from the
+// previous stage's rate when `ramp` is set
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct LoadStage {
+    #[serde(deserialize_with = "humantime_duration_deserializer")]
+    pub duration: Duration,
+    pub target_rps: u32,
+    #[serde(default)]
+    pub ramp: bool,
+}
+
+// Capacity search, raises the rate from `start_rps` until the p99 latency or
+// the error rate (in percent) of a step crosses its threshold
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct AutoThrottleConfig {
+    pub start_rps: u32,
+    pub step_rps: u32,
+    pub max_rps: Option<u32>,
+    pub min_step_rps: Option<u32>,
+    #[serde(deserialize_with = "humantime_duration_deserializer")]
+    pub step_duration: Duration,
+    #[serde(deserialize_with = "humantime_duration_deserializer")]
+    pub max_p99: Duration,
+    #[serde(default = "default_max_error_rate")]
+    pub max_error_rate: f64,
+}
+
+fn default_max_error_rate() -> f64 {
+    1.0
+}
+
+#[derive(Debug, Deserialize, Serialize, Default, Clone)]
+pub struct TlsConfig {
+    pub ca_cert: Option<String>,
+    #[serde(default)]
+    pub insecure_skip_verify: bool,
+    pub client_cert: Option<String>,
+    pub client_key: Option<String>,
+    pub client_pkcs12: Option<String>,
+    pub client_pkcs12_password: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(default)]
+pub struct ReconnectConfig {
+    pub max_attempts: u32,
+    #[serde(deserialize_with = "humantime_duration_deserializer")]
+    pub initial_backoff: Duration,
+    #[serde(deserialize_with = "humantime_duration_deserializer")]
+    pub max_backoff: Duration,
+    pub jitter: f64,
+}
+
+impl Default for ReconnectConfig {
+    fn default() -> Self {
+        ReconnectConfig {
+            max_attempts: 10,
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_secs(5),
+            jitter: 0.2,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct PingConfig {
+    #[serde(deserialize_with = "humantime_duration_deserializer")]
+    pub interval: Duration,
+    #[serde(deserialize_with = "humantime_duration_deserializer")]
+    pub timeout: Duration,
+}
+
+// HTTP/2 SETTINGS sent by the client, unset values keep the h2 defaults
+#[derive(Debug, Deserialize, Serialize, PartialEq, Default, Clone)]
+pub struct Http2Config {
+    pub initial_window_size: Option<u32>,
+    pub initial_connection_window_size: Option<u32>,
+    // Limits the streams the server may initiate (server push)
+    pub max_concurrent_streams: Option<u32>,
+    pub max_frame_size: Option<u32>,
+    pub max_header_list_size: Option<u32>,
+    pub enable_push: Option<bool>,
+}
+
 #[derive(Debug, Deserialize, Serialize, Clone)]
 pub struct Global {
     pub variables: Vec<variable::Variable>,
@@ -83,6 +313,46 @@ pub struct Scenario {
     pub pre_script: Option<Script>,
     #[serde(rename = "post-script")]
     pub post_script: Option<Script>,
+    pub repeat: Option<scenario::Repeat>,
+    #[serde(default)]
+    pub next: Vec<scenario::NextRule>,
+    // Wait before sending the scenario once the chain reaches it
+    pub delay: Option<Delay>,
+}
+
+#[derive(Debug, Deserialize, Serialize, PartialEq, Clone)]
+#[serde(untagged)]
+pub enum Delay {
+    Fixed(#[serde(deserialize_with = "humantime_duration_deserializer")] Duration),
+    // Drawn each time the chain reaches the scenario
+    Range {
+        #[serde(deserialize_with = "humantime_duration_deserializer")]
+        min: Duration,
+        #[serde(deserialize_with = "humantime_duration_deserializer")]
+        max: Duration,
+    },
+}
+
+impl Delay {
+    pub fn validate(&self, scenario: &str) -> Result<(), Box<dyn Error>> {
+        if let Delay::Range { min, max } = self {
+            if min > max {
+                return Err(format!(
+                    "Delay of scenario '{}' needs min to be less than or equal to max",
+                    scenario
+                )
+                .into());
+            }
+        }
+        Ok(())
+    }
+
+    pub fn sample(&self) -> Duration {
+        match self {
+            Delay::Fixed(delay) => *delay,
+            Delay::Range { min, max } => rand::thread_rng().gen_range(*min..=*max),
+        }
+    }
 }
 
 #[derive(Debug, Deserialize, Serialize, Clone)]
@@ -102,11 +372,42 @@ pub struct Request {
     pub method: String,
     pub path: String,
     pub headers: Option<Vec<HashMap<String, String>>>,
+    // One of `body`, `form`, `body_file` or `multipart`
     pub body: Option<String>,
+    #[serde(default)]
+    pub body_type: BodyType,
+    pub form: Option<Vec<FormField>>,
+    pub body_file: Option<String>,
+    pub multipart: Option<Vec<MultipartPart>>,
     #[serde(deserialize_with = "humantime_duration_deserializer")]
     pub timeout: Duration,
 }
 
+// `json` bodies are checked and sent compact, `text` bodies as they are
+#[derive(Debug, Deserialize, Serialize, PartialEq, Default, Copy, Clone)]
+#[serde(rename_all = "lowercase")]
+pub enum BodyType {
+    #[default]
+    Json,
+    Text,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct FormField {
+    pub name: String,
+    pub value: String,
+}
+
+// Part of a multipart/related body, with either a templated `body` or the
+// content of `file`
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct MultipartPart {
+    pub content_type: String,
+    pub content_id: Option<String>,
+    pub body: Option<String>,
+    pub file: Option<String>,
+}
+
 #[derive(Debug, Deserialize, Serialize, Clone)]
 pub struct Response {
     pub assert: ResponseAssert,
@@ -118,6 +419,9 @@ pub struct ResponseAssert {
     pub status: u16,
     pub headers: Option<Vec<scenario::HeadersAssert>>,
     pub body: Option<Vec<scenario::BodyAssert>>,
+    // Checked on the raw body, whatever its content type
+    pub body_regex: Option<String>,
+    pub body_length: Option<scenario::BodyLength>,
 }
 
 fn parse_override(override_str: &str) -> Result<(String, String), Box<dyn Error>> {
@@ -191,13 +495,15 @@ mod tests {
           batch_size: 5
           # auto_throttle: true
           base_url: "http://localhost:8080/"
+          http2:
+            initial_window_size: 1048576
+            enable_push: false
           global:
             variables:
               - name: COUNTER
                 value: 0
               - name: RANDOM
                 value: 0
-          # delay_between_scenario: 500ms
           scenarios:
             - name: createSubscriber
               request:
@@ -238,6 +544,21 @@ mod tests {
         assert_eq!(config.runner.duration, Duration::from_secs(10));
         assert_eq!(config.runner.batch_size, BatchSize::Fixed(5));
         assert_eq!(config.runner.base_url, "http://localhost:8080/".to_string());
+        assert_eq!(config.runner.connections_per_runner, 1);
+        assert_eq!(config.runner.mode, RunMode::Rate);
+        assert!(config.runner.arrival.is_none());
+        assert_eq!(config.runner.max_in_flight, None);
+        assert_eq!(config.runner.backpressure, Backpressure::Wait);
+        assert_eq!(config.runner.assert.on_failure, AssertFailure::Continue);
+        assert_eq!(config.runner.assert.max_failures, None);
+        assert_eq!(
+            config.runner.http2,
+            Http2Config {
+                initial_window_size: Some(1048576),
+                enable_push: Some(false),
+                ..Default::default()
+            }
+        );
         assert_eq!(config.runner.global.variables.len(), 2);
         assert_eq!(config.runner.global.variables[0].name, "COUNTER");
         assert_eq!(
@@ -284,5 +605,140 @@ mod tests {
         assert_eq!(config.runner.scenarios[1].request.headers, None);
         assert_eq!(config.runner.scenarios[1].request.body, None);
         assert_eq!(config.runner.scenarios[1].response.assert.status, 200);
+
+        // Scenarios alone are a single flow
+        assert!(config.runner.flows.is_none());
+        let flows = config.runner.flows();
+        assert_eq!(flows.len(), 1);
+        assert_eq!(flows[0].scenarios.len(), 2);
+        assert_eq!(
+            config.runner.scenario_names(),
+            vec!["createSubscriber", "querySubscriber"]
+        );
+    }
+
+    #[test]
+    fn test_yaml_flows() {
+        let yaml_str = r#"
+        target_rps: 100
+        duration: 10s
+        batch_size: Auto
+        base_url: "http://localhost:8080/"
+        global:
+          variables: []
+        flows:
+          - name: provision
+            weight: 70
+            scenarios:
+              - name: create
+                request:
+                  method: POST
+                  path: "/create"
+                  timeout: 3s
+                response:
+                  assert:
+                    status: 201
+              - name: release
+                request:
+                  method: DELETE
+                  path: "/release"
+                  timeout: 3s
+                response:
+                  assert:
+                    status: 200
+          - name: query
+            weight: 30
+            scenarios:
+              - name: query
+                request:
+                  method: GET
+                  path: "/query"
+                  timeout: 3s
+                response:
+                  assert:
+                    status: 200
+    "#;
+        let config: RunnerConfig = serde_yaml::from_str(yaml_str).unwrap();
+
+        assert!(config.scenarios.is_empty());
+        let flows = config.flows();
+        assert_eq!(flows.len(), 2);
+        assert_eq!(flows[0].name, "provision");
+        assert_eq!(flows[0].weight, 70);
+        assert_eq!(flows[1].scenarios[0].request.method, "GET");
+        assert_eq!(
+            config.scenario_names(),
+            vec!["provision/create", "provision/release", "query/query"]
+        );
+    }
+
+    #[test]
+    fn test_yaml_concurrency_mode() {
+        let mode: RunMode = serde_yaml::from_str("concurrency").unwrap();
+        assert_eq!(mode, RunMode::Concurrency);
+
+        let config: ConcurrencyConfig = serde_yaml::from_str("users: 50").unwrap();
+        assert_eq!(config.users, 50);
+        assert_eq!(config.think_time, Duration::ZERO);
+
+        let config: ConcurrencyConfig =
+            serde_yaml::from_str("users: 50\nthink_time: 250ms").unwrap();
+        assert_eq!(config.think_time, Duration::from_millis(250));
+    }
+
+    #[test]
+    fn test_yaml_arrival() {
+        let arrival: ArrivalConfig = serde_yaml::from_str("distribution: poisson").unwrap();
+        assert_eq!(arrival.distribution, ArrivalDistribution::Poisson);
+        assert_eq!(arrival.jitter, 0.5);
+
+        let arrival: ArrivalConfig =
+            serde_yaml::from_str("distribution: uniform\njitter: 0.2").unwrap();
+        assert_eq!(arrival.distribution, ArrivalDistribution::Uniform);
+        assert_eq!(arrival.jitter, 0.2);
+    }
+
+    #[test]
+    fn test_yaml_backpressure() {
+        let backpressure: Backpressure = serde_yaml::from_str("drop").unwrap();
+        assert_eq!(backpressure, Backpressure::Drop);
+        assert!(serde_yaml::from_str::<Backpressure>("block").is_err());
+    }
+
+    #[test]
+    fn test_yaml_assert() {
+        let config: AssertConfig = serde_yaml::from_str(
+            r#"
+            on_failure: abort_chain
+            max_failures: 10
+            max_error_rate: 5.0
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.on_failure, AssertFailure::AbortChain);
+        assert_eq!(config.max_failures, Some(10));
+        assert_eq!(config.max_error_rate, Some(5.0));
+
+        let config: AssertConfig = serde_yaml::from_str("max_failures: 1").unwrap();
+        assert_eq!(config.on_failure, AssertFailure::Continue);
+        assert!(serde_yaml::from_str::<AssertFailure>("panic").is_err());
+    }
+
+    #[test]
+    fn test_yaml_delay() {
+        let delay: Delay = serde_yaml::from_str("500ms").unwrap();
+        assert_eq!(delay, Delay::Fixed(Duration::from_millis(500)));
+        assert_eq!(delay.sample(), Duration::from_millis(500));
+
+        let delay: Delay = serde_yaml::from_str("{ min: 1s, max: 3s }").unwrap();
+        assert!(delay.validate("update").is_ok());
+        for _ in 0..100 {
+            let sample = delay.sample();
+            assert!(sample >= Duration::from_secs(1) && sample <= Duration::from_secs(3));
+        }
+
+        let delay: Delay = serde_yaml::from_str("{ min: 2s, max: 1s }").unwrap();
+        assert!(delay.validate("update").is_err());
+        assert!(serde_yaml::from_str::<Delay>("soon").is_err());
     }
 }
@@ -0,0 +1,1120 @@
+use crate::config::Http2Config;
+use crate::config::PingConfig;
+use crate::config::ReconnectConfig;
+use crate::config::TlsConfig;
+use crate::error::Error::ConnectionError;
+use crate::error::Error::HandshakeError;
+use crate::stats::ApiStats;
+use crate::tls;
+use bytes::Bytes;
+use h2::client;
+use h2::client::SendRequest;
+use h2::Ping;
+use h2::PingPong;
+use http::Uri;
+use rand::Rng;
+use std::error::Error;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::time::Duration;
+use std::time::Instant;
+use tokio::io::AsyncRead;
+use tokio::io::AsyncWrite;
+use tokio::net::TcpStream;
+use tokio::sync::mpsc::unbounded_channel;
+use tokio::sync::mpsc::UnboundedReceiver;
+use tokio::sync::mpsc::UnboundedSender;
+use tokio::task::AbortHandle;
+use tokio_rustls::rustls::pki_types::ServerName;
+use tokio_rustls::TlsConnector;
+
+pub struct Connector {
+    address: String,
+    server_name: ServerName<'static>,
+    tls: Option<TlsConnector>,
+    builder: client::Builder,
+}
+
+impl Connector {
+    pub fn new(
+        base_url: &str,
+        tls_config: Option<&TlsConfig>,
+        http2_config: &Http2Config,
+    ) -> Result<Connector, Box<dyn Error>> {
+        let uri: Uri = base_url
+            .parse()
+            .map_err(|e| format!("Invalid base_url '{}': {}", base_url, e))?;
+
+        let secure = match uri.scheme_str() {
+            Some("https") => true,
+            Some("http") | None => false,
+            Some(scheme) => return Err(format!("Unsupported scheme '{}'", scheme).into()),
+        };
+
+        let host = uri
+            .host()
+            .ok_or_else(|| format!("Missing host in base_url '{}'", base_url))?;
+        let port = uri.port_u16().unwrap_or(if secure { 443 } else { 80 });
+        let address = format!("{}:{}", host, port);
+
+        // IPv6 literal comes with brackets from the uri
+        let server_name = host.trim_start_matches('[').trim_end_matches(']');
+        let server_name = ServerName::try_from(server_name.to_string())?;
+
+        let tls = if secure {
+            let default_config = TlsConfig::default();
+            let tls_config = tls_config.unwrap_or(&default_config);
+            let client_config = tls::client_config(tls_config)?;
+            Some(TlsConnector::from(Arc::new(client_config)))
+        } else {
+            if tls_config.is_some() {
+                log::warn!(
+                    "TLS config is ignored for non https base_url '{}'",
+                    base_url
+                );
+            }
+            None
+        };
+
+        Ok(Connector {
+            address,
+            server_name,
+            tls,
+            builder: http2_builder(http2_config)?,
+        })
+    }
+
+    pub async fn connect(&self) -> Result<Connection, Box<dyn Error>> {
+        let tcp = TcpStream::connect(&self.address)
+            .await
+            .map_err(|e| ConnectionError(format!("{}: {}", self.address, e)))?;
+
+        match &self.tls {
+            Some(tls) => {
+                let stream = tls
+                    .connect(self.server_name.clone(), tcp)
+                    .await
+                    .map_err(|e| HandshakeError(format!("TLS with {}: {}", self.address, e)))?;
+
+                let alpn = stream.get_ref().1.alpn_protocol();
+                if alpn != Some(tls::ALPN_H2) {
+                    return Err(HandshakeError(format!(
+                        "Server {} did not negotiate h2 via ALPN, got: {:?}",
+                        self.address,
+                        alpn.map(String::from_utf8_lossy)
+                    ))
+                    .into());
+                }
+
+                self.handshake(stream).await
+            }
+            None => self.handshake(tcp).await,
+        }
+    }
+
+    async fn handshake<T>(&self, io: T) -> Result<Connection, Box<dyn Error>>
+    where
+        T: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    {
+        let (client, mut h2) = self
+            .builder
+            .handshake(io)
+            .await
+            .map_err(|e| HandshakeError(format!("HTTP/2: {}", e)))?;
+
+        let ping_pong = h2.ping_pong();
+
+        // Flag the connection as closed once the server goes away (GOAWAY, reset, restart)
+        let closed = Arc::new(AtomicBool::new(false));
+        let address = self.address.clone();
+        let connection_closed = Arc::clone(&closed);
+        let driver = tokio::task::spawn(async move {
+            match h2.await {
+                Ok(_) => log::warn!("Connection to {} closed", address),
+                Err(e) => log::warn!("Connection to {} closed: {}", address, e),
+            }
+            connection_closed.store(true, Ordering::Relaxed);
+        });
+
+        Ok(Connection {
+            client,
+            open_streams: Arc::new(AtomicUsize::new(0)),
+            closed,
+            ping_pong,
+            driver: driver.abort_handle(),
+        })
+    }
+
+    pub fn address(&self) -> &str {
+        &self.address
+    }
+}
+
+// Maps the `http2` config onto the SETTINGS sent during the client handshake
+fn http2_builder(config: &Http2Config) -> Result<client::Builder, Box<dyn Error>> {
+    const MAX_WINDOW_SIZE: u32 = (1 << 31) - 1;
+    const MIN_FRAME_SIZE: u32 = 1 << 14;
+    const MAX_FRAME_SIZE: u32 = (1 << 24) - 1;
+
+    let mut builder = client::Builder::new();
+
+    if let Some(size) = config.initial_window_size {
+        if size > MAX_WINDOW_SIZE {
+            return Err(format!(
+                "http2.initial_window_size must be at most {}",
+                MAX_WINDOW_SIZE
+            )
+            .into());
+        }
+        builder.initial_window_size(size);
+    }
+    if let Some(size) = config.initial_connection_window_size {
+        if size > MAX_WINDOW_SIZE {
+            return Err(format!(
+                "http2.initial_connection_window_size must be at most {}",
+                MAX_WINDOW_SIZE
+            )
+            .into());
+        }
+        builder.initial_connection_window_size(size);
+    }
+    if let Some(max) = config.max_concurrent_streams {
+        builder.max_concurrent_streams(max);
+    }
+    if let Some(max) = config.max_frame_size {
+        if !(MIN_FRAME_SIZE..=MAX_FRAME_SIZE).contains(&max) {
+            return Err(format!(
+                "http2.max_frame_size must be between {} and {}",
+                MIN_FRAME_SIZE, MAX_FRAME_SIZE
+            )
+            .into());
+        }
+        builder.max_frame_size(max);
+    }
+    if let Some(max) = config.max_header_list_size {
+        builder.max_header_list_size(max);
+    }
+    if let Some(enabled) = config.enable_push {
+        builder.enable_push(enabled);
+    }
+
+    Ok(builder)
+}
+
+pub struct Connection {
+    client: SendRequest<Bytes>,
+    open_streams: Arc<AtomicUsize>,
+    closed: Arc<AtomicBool>,
+    ping_pong: Option<PingPong>,
+    driver: AbortHandle,
+}
+
+impl Connection {
+    pub fn is_closed(&self) -> bool {
+        self.closed.load(Ordering::Relaxed)
+    }
+
+    pub fn mark_closed(&self) {
+        self.closed.store(true, Ordering::Relaxed);
+    }
+
+    pub fn client(&mut self) -> &mut SendRequest<Bytes> {
+        &mut self.client
+    }
+
+    pub fn open_streams(&self) -> usize {
+        self.open_streams.load(Ordering::Relaxed)
+    }
+
+    // Waits until the server's MAX_CONCURRENT_STREAMS allows another stream,
+    // returns whether the send had to wait
+    pub async fn ready(&mut self) -> Result<bool, h2::Error> {
+        let mut delayed = false;
+        std::future::poll_fn(|cx| {
+            let poll = self.client.poll_ready(cx);
+            delayed |= poll.is_pending();
+            poll
+        })
+        .await?;
+        Ok(delayed)
+    }
+
+    // Counts a stream as open on this connection until the guard is dropped
+    pub fn stream_guard(&self) -> StreamGuard {
+        self.open_streams.fetch_add(1, Ordering::Relaxed);
+        StreamGuard(Arc::clone(&self.open_streams))
+    }
+
+    // Sends a PING every `interval` and records its RTT, a PING left
+    // unanswered for `timeout` closes the connection so it gets reconnected
+    fn start_keepalive(&mut self, config: &PingConfig, api_stats: Arc<ApiStats>) {
+        let Some(mut ping_pong) = self.ping_pong.take() else {
+            return;
+        };
+        let closed = Arc::clone(&self.closed);
+        let driver = self.driver.clone();
+        let config = config.clone();
+
+        tokio::task::spawn(async move {
+            let mut interval = tokio::time::interval(config.interval);
+            interval.tick().await;
+
+            loop {
+                interval.tick().await;
+                if closed.load(Ordering::Relaxed) {
+                    break;
+                }
+
+                let start = Instant::now();
+                match tokio::time::timeout(config.timeout, ping_pong.ping(Ping::opaque())).await {
+                    Ok(Ok(_)) => api_stats.inc_ping(start.elapsed().as_micros() as u64),
+                    Ok(Err(e)) => {
+                        log::debug!("PING failed: {}", e);
+                        break;
+                    }
+                    Err(_) => {
+                        log::warn!(
+                            "PING timed out after {:?}, closing connection",
+                            config.timeout
+                        );
+                        api_stats.inc_ping_timeout();
+                        closed.store(true, Ordering::Relaxed);
+                        driver.abort();
+                        break;
+                    }
+                }
+            }
+        });
+    }
+}
+
+pub struct StreamGuard(Arc<AtomicUsize>);
+
+impl Drop for StreamGuard {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+pub struct ConnectionPool {
+    connector: Arc<Connector>,
+    reconnect: ReconnectConfig,
+    ping: Option<PingConfig>,
+    api_stats: Arc<ApiStats>,
+    connections: Vec<Connection>,
+    // Slots being reconnected in the background
+    reconnecting: Vec<bool>,
+    // Slots whose reconnect gave up after `max_attempts`, left closed
+    gave_up: Vec<bool>,
+    // Reconnected connections by slot, None once a reconnect gave up
+    reconnected_tx: UnboundedSender<(usize, Option<Connection>)>,
+    reconnected_rx: UnboundedReceiver<(usize, Option<Connection>)>,
+}
+
+impl ConnectionPool {
+    pub async fn connect(
+        connector: Arc<Connector>,
+        size: u16,
+        reconnect: ReconnectConfig,
+        ping: Option<PingConfig>,
+        api_stats: Arc<ApiStats>,
+    ) -> Result<ConnectionPool, Box<dyn Error>> {
+        if size == 0 {
+            return Err("connections_per_runner must be at least 1".into());
+        }
+
+        let mut connections = vec![];
+        for _ in 0..size {
+            let mut connection = connector.connect().await?;
+            if let Some(ping) = &ping {
+                connection.start_keepalive(ping, Arc::clone(&api_stats));
+            }
+            connections.push(connection);
+        }
+        api_stats.set_connections(connections.iter().map(|c| Arc::clone(&c.closed)).collect());
+        log::info!(
+            "Connected {} connection(s) to {}",
+            size,
+            connector.address()
+        );
+
+        let (reconnected_tx, reconnected_rx) = unbounded_channel();
+        Ok(ConnectionPool {
+            connector,
+            reconnect,
+            ping,
+            api_stats,
+            reconnecting: vec![false; connections.len()],
+            gave_up: vec![false; connections.len()],
+            connections,
+            reconnected_tx,
+            reconnected_rx,
+        })
+    }
+
+    // Open connection with the fewest open streams, first one wins on a tie.
+    // Closed connections are re-established in the background, None until
+    // one is open again if all of them are closed. Fails once every slot
+    // gave up reconnecting.
+    pub fn least_loaded(&mut self) -> Result<Option<&mut Connection>, Box<dyn Error>> {
+        self.reconnect_closed();
+
+        if self.gave_up.iter().all(|gave_up| *gave_up) {
+            return Err(ConnectionError(format!(
+                "Gave up reconnecting all {} connection(s) to {}",
+                self.connections.len(),
+                self.connector.address()
+            ))
+            .into());
+        }
+
+        Ok(self
+            .connections
+            .iter_mut()
+            .filter(|c| !c.is_closed())
+            .min_by_key(|c| c.open_streams()))
+    }
+
+    fn reconnect_closed(&mut self) {
+        let mut reconnected = false;
+        while let Ok((index, connection)) = self.reconnected_rx.try_recv() {
+            self.reconnecting[index] = false;
+            let Some(mut connection) = connection else {
+                self.gave_up[index] = true;
+                continue;
+            };
+            if let Some(ping) = &self.ping {
+                connection.start_keepalive(ping, Arc::clone(&self.api_stats));
+            }
+            self.connections[index] = connection;
+            self.api_stats.inc_reconnect();
+            reconnected = true;
+        }
+        if reconnected {
+            self.api_stats.set_connections(
+                self.connections
+                    .iter()
+                    .map(|c| Arc::clone(&c.closed))
+                    .collect(),
+            );
+        }
+
+        for index in 0..self.connections.len() {
+            if !self.connections[index].is_closed()
+                || self.reconnecting[index]
+                || self.gave_up[index]
+            {
+                continue;
+            }
+            self.reconnecting[index] = true;
+            let connector = Arc::clone(&self.connector);
+            let policy = self.reconnect.clone();
+            let tx = self.reconnected_tx.clone();
+            tokio::spawn(async move {
+                let connection = match reconnect(&connector, &policy).await {
+                    Ok(connection) => Some(connection),
+                    Err(e) => {
+                        log::error!("{}", e);
+                        None
+                    }
+                };
+                // Pool is gone once the run is over
+                let _ = tx.send((index, connection));
+            });
+        }
+    }
+}
+
+async fn reconnect(
+    connector: &Connector,
+    policy: &ReconnectConfig,
+) -> Result<Connection, Box<dyn Error>> {
+    for attempt in 0..policy.max_attempts {
+        let delay = backoff(policy, attempt);
+        log::warn!(
+            "Reconnecting to {} in {:?} (attempt {}/{})",
+            connector.address(),
+            delay,
+            attempt + 1,
+            policy.max_attempts
+        );
+        tokio::time::sleep(delay).await;
+
+        match connector.connect().await {
+            Ok(connection) => {
+                log::info!("Reconnected to {}", connector.address());
+                return Ok(connection);
+            }
+            Err(e) => log::warn!("Reconnect failed: {}", e),
+        }
+    }
+
+    Err(ConnectionError(format!(
+        "Gave up reconnecting to {} after {} attempt(s)",
+        connector.address(),
+        policy.max_attempts
+    ))
+    .into())
+}
+
+// Exponential backoff capped at `max_backoff`, randomized by +/- `jitter`
+fn backoff(policy: &ReconnectConfig, attempt: u32) -> Duration {
+    let backoff = policy
+        .initial_backoff
+        .saturating_mul(2u32.saturating_pow(attempt))
+        .min(policy.max_backoff);
+
+    if policy.jitter > 0.0 {
+        let factor = rand::thread_rng().gen_range(1.0 - policy.jitter..=1.0 + policy.jitter);
+        backoff.mul_f64(factor.max(0.0))
+    } else {
+        backoff
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http_api::send_request;
+    use crate::http_api::ErrorKind;
+    use crate::http_api::HttpError;
+    use crate::http_api::HttpRequest;
+    use http::Request;
+    use http::Response;
+    use http::StatusCode;
+    use rcgen::BasicConstraints;
+    use rcgen::CertificateParams;
+    use rcgen::CertifiedKey;
+    use rcgen::IsCa;
+    use rcgen::KeyPair;
+    use tokio::net::TcpListener;
+    use tokio_rustls::rustls::pki_types::CertificateDer;
+    use tokio_rustls::rustls::pki_types::PrivateKeyDer;
+    use tokio_rustls::rustls::pki_types::PrivatePkcs8KeyDer;
+    use tokio_rustls::rustls::server::WebPkiClientVerifier;
+    use tokio_rustls::rustls::RootCertStore;
+    use tokio_rustls::rustls::ServerConfig;
+    use tokio_rustls::TlsAcceptor;
+
+    enum ServerMode {
+        Serve,
+        // Sends GOAWAY after the given number of requests
+        GoAwayAfter(usize),
+        // Accepts a single connection, which sends GOAWAY after the first request
+        SingleConnection,
+        // Stops reading after the handshake, PINGs are never answered
+        Stall,
+        // MAX_CONCURRENT_STREAMS of 1, answers each request after the delay
+        SingleStream(Duration),
+        // Resets every stream with RST_STREAM
+        Reset(h2::Reason),
+    }
+
+    // Self-signed h2 server answering every request with 200, requires a
+    // client certificate signed by `client_ca` if given
+    async fn start_tls_server(
+        alpn: Vec<Vec<u8>>,
+        client_ca: Option<CertificateDer<'static>>,
+        mode: ServerMode,
+    ) -> (u16, CertifiedKey) {
+        let certified = rcgen::generate_simple_self_signed(vec!["localhost".into()]).unwrap();
+        let cert = certified.cert.der().clone();
+        let key =
+            PrivateKeyDer::Pkcs8(PrivatePkcs8KeyDer::from(certified.key_pair.serialize_der()));
+
+        let provider = Arc::new(tokio_rustls::rustls::crypto::ring::default_provider());
+        let builder = ServerConfig::builder_with_provider(Arc::clone(&provider))
+            .with_safe_default_protocol_versions()
+            .unwrap();
+        let builder = match client_ca {
+            Some(client_ca) => {
+                let mut roots = RootCertStore::empty();
+                roots.add(client_ca).unwrap();
+                let verifier =
+                    WebPkiClientVerifier::builder_with_provider(Arc::new(roots), provider)
+                        .build()
+                        .unwrap();
+                builder.with_client_cert_verifier(verifier)
+            }
+            None => builder.with_no_client_auth(),
+        };
+        let mut server_config = builder.with_single_cert(vec![cert], key).unwrap();
+        server_config.alpn_protocols = alpn;
+        let acceptor = TlsAcceptor::from(Arc::new(server_config));
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        let mode = Arc::new(mode);
+        tokio::spawn(async move {
+            while let Ok((tcp, _)) = listener.accept().await {
+                let acceptor = acceptor.clone();
+                let single = matches!(*mode, ServerMode::SingleConnection);
+                let mode = Arc::clone(&mode);
+                tokio::spawn(async move {
+                    let Ok(stream) = acceptor.accept(tcp).await else {
+                        return;
+                    };
+                    let mut builder = h2::server::Builder::new();
+                    if let ServerMode::SingleStream(_) = *mode {
+                        builder.max_concurrent_streams(1);
+                    }
+                    let Ok(mut conn) = builder.handshake::<_, Bytes>(stream).await else {
+                        return;
+                    };
+                    if let ServerMode::Stall = *mode {
+                        tokio::time::sleep(Duration::from_secs(60)).await;
+                        return;
+                    }
+                    let mut count = 0;
+                    while let Some(Ok((_, mut respond))) = conn.accept().await {
+                        if let ServerMode::SingleStream(delay) = *mode {
+                            tokio::spawn(async move {
+                                tokio::time::sleep(delay).await;
+                                let response = Response::builder().status(200).body(()).unwrap();
+                                let _ = respond.send_response(response, true);
+                            });
+                            continue;
+                        }
+                        if let ServerMode::Reset(reason) = *mode {
+                            respond.send_reset(reason);
+                            continue;
+                        }
+                        let response = Response::builder().status(200).body(()).unwrap();
+                        respond.send_response(response, true).unwrap();
+
+                        count += 1;
+                        let go_away = match *mode {
+                            ServerMode::GoAwayAfter(n) => count == n,
+                            ServerMode::SingleConnection => true,
+                            _ => false,
+                        };
+                        if go_away {
+                            conn.graceful_shutdown();
+                        }
+                    }
+                });
+                // Further connections are refused
+                if single {
+                    break;
+                }
+            }
+        });
+
+        (port, certified)
+    }
+
+    async fn get(connection: &Connection, port: u16) -> Result<StatusCode, h2::Error> {
+        let mut client = connection.client.clone().ready().await?;
+        let request = Request::builder()
+            .uri(format!("https://localhost:{}/", port))
+            .body(())
+            .unwrap();
+        let (response, _) = client.send_request(request, true)?;
+        Ok(response.await?.status())
+    }
+
+    fn temp_file(name: &str, port: u16, contents: &[u8]) -> String {
+        let path = std::env::temp_dir().join(format!("http2-load-generator-{}-{}", port, name));
+        std::fs::write(&path, contents).unwrap();
+        path.to_string_lossy().into()
+    }
+
+    // CA and a client certificate signed by it
+    fn client_ca_and_cert() -> (CertificateDer<'static>, CertifiedKey) {
+        let mut ca_params = CertificateParams::new(vec![]).unwrap();
+        ca_params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+        let ca_key = KeyPair::generate().unwrap();
+        let ca_cert = ca_params.self_signed(&ca_key).unwrap();
+
+        let client_key = KeyPair::generate().unwrap();
+        let client_cert = CertificateParams::new(vec!["smf.local".into()])
+            .unwrap()
+            .signed_by(&client_key, &ca_cert, &ca_key)
+            .unwrap();
+
+        (
+            ca_cert.der().clone(),
+            CertifiedKey {
+                cert: client_cert,
+                key_pair: client_key,
+            },
+        )
+    }
+
+    #[tokio::test]
+    async fn test_connect_tls_with_ca_cert() {
+        let (port, certified) =
+            start_tls_server(vec![b"h2".to_vec()], None, ServerMode::Serve).await;
+
+        let ca_cert = temp_file("ca.pem", port, certified.cert.pem().as_bytes());
+
+        let tls_config = TlsConfig {
+            ca_cert: Some(ca_cert.clone()),
+            ..Default::default()
+        };
+        let base_url = format!("https://localhost:{}", port);
+        let connector =
+            Connector::new(&base_url, Some(&tls_config), &Http2Config::default()).unwrap();
+        let connection = connector.connect().await.unwrap();
+
+        assert_eq!(get(&connection, port).await.unwrap(), StatusCode::OK);
+        std::fs::remove_file(ca_cert).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_connect_tls_insecure_skip_verify() {
+        let (port, _) = start_tls_server(vec![b"h2".to_vec()], None, ServerMode::Serve).await;
+
+        let base_url = format!("https://localhost:{}", port);
+
+        // Self-signed certificate is rejected by default
+        let connector = Connector::new(&base_url, None, &Http2Config::default()).unwrap();
+        assert!(connector.connect().await.is_err());
+
+        let tls_config = TlsConfig {
+            insecure_skip_verify: true,
+            ..Default::default()
+        };
+        let connector =
+            Connector::new(&base_url, Some(&tls_config), &Http2Config::default()).unwrap();
+        let connection = connector.connect().await.unwrap();
+
+        assert_eq!(get(&connection, port).await.unwrap(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_connect_tls_without_h2_alpn() {
+        let (port, _) = start_tls_server(vec![], None, ServerMode::Serve).await;
+
+        let tls_config = TlsConfig {
+            insecure_skip_verify: true,
+            ..Default::default()
+        };
+        let base_url = format!("https://localhost:{}", port);
+        let connector =
+            Connector::new(&base_url, Some(&tls_config), &Http2Config::default()).unwrap();
+
+        match connector.connect().await {
+            Ok(_) => panic!("Expected error"),
+            Err(err) => assert!(err.to_string().contains("did not negotiate h2")),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_connect_mtls_with_pem() {
+        let (client_ca, client) = client_ca_and_cert();
+        let (port, _) =
+            start_tls_server(vec![b"h2".to_vec()], Some(client_ca), ServerMode::Serve).await;
+        let base_url = format!("https://localhost:{}", port);
+
+        // Server rejects the stream without a client certificate
+        let tls_config = TlsConfig {
+            insecure_skip_verify: true,
+            ..Default::default()
+        };
+        let connector =
+            Connector::new(&base_url, Some(&tls_config), &Http2Config::default()).unwrap();
+        if let Ok(connection) = connector.connect().await {
+            assert!(get(&connection, port).await.is_err());
+        }
+
+        let client_cert = temp_file("client.pem", port, client.cert.pem().as_bytes());
+        let client_key = temp_file(
+            "client.key",
+            port,
+            client.key_pair.serialize_pem().as_bytes(),
+        );
+        let tls_config = TlsConfig {
+            insecure_skip_verify: true,
+            client_cert: Some(client_cert.clone()),
+            client_key: Some(client_key.clone()),
+            ..Default::default()
+        };
+        let connector =
+            Connector::new(&base_url, Some(&tls_config), &Http2Config::default()).unwrap();
+        let connection = connector.connect().await.unwrap();
+
+        assert_eq!(get(&connection, port).await.unwrap(), StatusCode::OK);
+        std::fs::remove_file(client_cert).unwrap();
+        std::fs::remove_file(client_key).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_connect_mtls_with_pkcs12() {
+        let (client_ca, client) = client_ca_and_cert();
+        let (port, _) =
+            start_tls_server(vec![b"h2".to_vec()], Some(client_ca), ServerMode::Serve).await;
+
+        let mut keystore = p12_keystore::KeyStore::new();
+        let chain = p12_keystore::PrivateKeyChain::new(
+            "smf",
+            p12_keystore::PrivateKey::from_der(&client.key_pair.serialize_der()).unwrap(),
+            vec![p12_keystore::Certificate::from_der(client.cert.der()).unwrap()],
+        );
+        keystore.add_entry("smf", p12_keystore::KeyStoreEntry::PrivateKeyChain(chain));
+        let pkcs12 = keystore.writer("secret").write().unwrap();
+        let client_pkcs12 = temp_file("client.p12", port, &pkcs12);
+
+        let tls_config = TlsConfig {
+            insecure_skip_verify: true,
+            client_pkcs12: Some(client_pkcs12.clone()),
+            client_pkcs12_password: Some("secret".into()),
+            ..Default::default()
+        };
+        let base_url = format!("https://localhost:{}", port);
+        let connector =
+            Connector::new(&base_url, Some(&tls_config), &Http2Config::default()).unwrap();
+        let connection = connector.connect().await.unwrap();
+
+        assert_eq!(get(&connection, port).await.unwrap(), StatusCode::OK);
+
+        // A PEM key next to the PKCS#12 file is rejected, not taken as half a pair
+        let tls_config = TlsConfig {
+            client_key: Some("./client.key".into()),
+            ..tls_config
+        };
+        let err = Connector::new(&base_url, Some(&tls_config), &Http2Config::default())
+            .err()
+            .unwrap();
+        assert_eq!(
+            err.to_string(),
+            "client_pkcs12 and client_cert/client_key are mutually exclusive"
+        );
+        std::fs::remove_file(client_pkcs12).unwrap();
+    }
+
+    fn insecure_connector(port: u16) -> Arc<Connector> {
+        let tls_config = TlsConfig {
+            insecure_skip_verify: true,
+            ..Default::default()
+        };
+        let base_url = format!("https://localhost:{}", port);
+        Arc::new(Connector::new(&base_url, Some(&tls_config), &Http2Config::default()).unwrap())
+    }
+
+    #[tokio::test]
+    async fn test_connection_pool_least_loaded() {
+        let (port, _) = start_tls_server(vec![b"h2".to_vec()], None, ServerMode::Serve).await;
+
+        let connector = insecure_connector(port);
+        let api_stats = Arc::new(ApiStats::new());
+        let reconnect = ReconnectConfig::default();
+        let mut pool = ConnectionPool::connect(connector.clone(), 2, reconnect, None, api_stats)
+            .await
+            .unwrap();
+
+        let guard1 = pool.least_loaded().unwrap().unwrap().stream_guard();
+        assert_eq!(pool.least_loaded().unwrap().unwrap().open_streams(), 0);
+        let guard2 = pool.least_loaded().unwrap().unwrap().stream_guard();
+        assert_eq!(pool.least_loaded().unwrap().unwrap().open_streams(), 1);
+
+        drop(guard1);
+        assert_eq!(pool.least_loaded().unwrap().unwrap().open_streams(), 0);
+        drop(guard2);
+
+        let api_stats = Arc::new(ApiStats::new());
+        let reconnect = ReconnectConfig::default();
+        assert!(
+            ConnectionPool::connect(connector, 0, reconnect, None, api_stats)
+                .await
+                .is_err()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_connection_pool_reconnect() {
+        let (port, _) =
+            start_tls_server(vec![b"h2".to_vec()], None, ServerMode::GoAwayAfter(1)).await;
+
+        let api_stats = Arc::new(ApiStats::new());
+        let reconnect = ReconnectConfig {
+            initial_backoff: Duration::from_millis(1),
+            ..Default::default()
+        };
+        let mut pool = ConnectionPool::connect(
+            insecure_connector(port),
+            1,
+            reconnect,
+            None,
+            Arc::clone(&api_stats),
+        )
+        .await
+        .unwrap();
+
+        // Server sends GOAWAY after the first request
+        let connection = pool.least_loaded().unwrap().unwrap();
+        assert_eq!(get(connection, port).await.unwrap(), StatusCode::OK);
+        while !connection.is_closed() {
+            tokio::time::sleep(Duration::from_millis(1)).await;
+        }
+
+        // No open connection until the background reconnect is done
+        while pool.least_loaded().unwrap().is_none() {
+            tokio::time::sleep(Duration::from_millis(1)).await;
+        }
+        let connection = pool.least_loaded().unwrap().unwrap();
+        assert!(!connection.is_closed());
+        assert_eq!(get(connection, port).await.unwrap(), StatusCode::OK);
+        assert_eq!(api_stats.get_reconnect(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_connection_pool_reconnect_in_background() {
+        let (port, _) =
+            start_tls_server(vec![b"h2".to_vec()], None, ServerMode::GoAwayAfter(1)).await;
+
+        // Reconnect is far off, the pool keeps using the open connection
+        let reconnect = ReconnectConfig {
+            initial_backoff: Duration::from_secs(60),
+            jitter: 0.0,
+            ..Default::default()
+        };
+        let api_stats = Arc::new(ApiStats::new());
+        let mut pool = ConnectionPool::connect(
+            insecure_connector(port),
+            2,
+            reconnect,
+            None,
+            Arc::clone(&api_stats),
+        )
+        .await
+        .unwrap();
+
+        let connection = pool.least_loaded().unwrap().unwrap();
+        assert_eq!(get(connection, port).await.unwrap(), StatusCode::OK);
+        while !connection.is_closed() {
+            tokio::time::sleep(Duration::from_millis(1)).await;
+        }
+
+        let connection = pool.least_loaded().unwrap().unwrap();
+        assert!(!connection.is_closed());
+        assert_eq!(get(connection, port).await.unwrap(), StatusCode::OK);
+        while !connection.is_closed() {
+            tokio::time::sleep(Duration::from_millis(1)).await;
+        }
+        assert!(pool.least_loaded().unwrap().is_none());
+        assert_eq!(api_stats.get_reconnect(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_connection_pool_reconnect_gave_up() {
+        let (port, _) =
+            start_tls_server(vec![b"h2".to_vec()], None, ServerMode::SingleConnection).await;
+
+        let reconnect = ReconnectConfig {
+            max_attempts: 2,
+            initial_backoff: Duration::from_millis(1),
+            ..Default::default()
+        };
+        let api_stats = Arc::new(ApiStats::new());
+        let mut pool = ConnectionPool::connect(
+            insecure_connector(port),
+            1,
+            reconnect,
+            None,
+            Arc::clone(&api_stats),
+        )
+        .await
+        .unwrap();
+
+        let connection = pool.least_loaded().unwrap().unwrap();
+        assert_eq!(get(connection, port).await.unwrap(), StatusCode::OK);
+        while !connection.is_closed() {
+            tokio::time::sleep(Duration::from_millis(1)).await;
+        }
+
+        // Closed while reconnecting, then the pool fails for good
+        let e = loop {
+            match pool.least_loaded() {
+                Ok(connection) => assert!(connection.is_none()),
+                Err(e) => break e,
+            }
+            tokio::time::sleep(Duration::from_millis(1)).await;
+        };
+        assert!(matches!(
+            e.downcast_ref::<crate::error::Error>(),
+            Some(ConnectionError(_))
+        ));
+        assert!(pool.least_loaded().is_err());
+        assert!(!pool.reconnecting[0]);
+        assert_eq!(api_stats.get_reconnect(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_connection_ready_max_concurrent_streams() {
+        let delay = Duration::from_millis(50);
+        let (port, _) =
+            start_tls_server(vec![b"h2".to_vec()], None, ServerMode::SingleStream(delay)).await;
+        let mut connection = insecure_connector(port).connect().await.unwrap();
+
+        // Server SETTINGS are known once a request went through
+        assert_eq!(get(&connection, port).await.unwrap(), StatusCode::OK);
+        assert!(!connection.ready().await.unwrap());
+
+        // Second stream is queued by h2 until the first one completes, sending
+        // without waiting for it would be rejected
+        let request = || {
+            Request::builder()
+                .uri(format!("https://localhost:{}/", port))
+                .body(())
+                .unwrap()
+        };
+        let (first, _) = connection.client().send_request(request(), true).unwrap();
+        connection.ready().await.unwrap();
+        let (second, _) = connection.client().send_request(request(), true).unwrap();
+
+        let start = Instant::now();
+        assert!(connection.ready().await.unwrap());
+        assert!(start.elapsed() >= delay / 2);
+        assert_eq!(first.await.unwrap().status(), StatusCode::OK);
+        assert_eq!(second.await.unwrap().status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_send_request_failures() {
+        let request = |port, timeout| HttpRequest {
+            uri: format!("https://localhost:{}/", port),
+            method: http::Method::GET,
+            headers: None,
+            body: None,
+            timeout,
+        };
+
+        let (port, _) = start_tls_server(
+            vec![b"h2".to_vec()],
+            None,
+            ServerMode::Reset(h2::Reason::REFUSED_STREAM),
+        )
+        .await;
+        let mut connection = insecure_connector(port).connect().await.unwrap();
+        connection.ready().await.unwrap();
+        let response = send_request(connection.client(), request(port, Duration::from_secs(3)))
+            .await
+            .unwrap();
+        let e = response.await.unwrap().err().unwrap();
+        assert!(matches!(e, HttpError::Reset(h2::Reason::REFUSED_STREAM)));
+        assert_eq!(e.kind(), ErrorKind::Reset);
+
+        let delay = Duration::from_millis(500);
+        let (port, _) =
+            start_tls_server(vec![b"h2".to_vec()], None, ServerMode::SingleStream(delay)).await;
+        let mut connection = insecure_connector(port).connect().await.unwrap();
+        connection.ready().await.unwrap();
+        let timeout = Duration::from_millis(20);
+        let response = send_request(connection.client(), request(port, timeout))
+            .await
+            .unwrap();
+        let e = response.await.unwrap().err().unwrap();
+        assert!(matches!(e, HttpError::Timeout(t) if t == timeout));
+        assert_eq!(e.kind(), ErrorKind::Timeout);
+    }
+
+    #[tokio::test]
+    async fn test_connection_pool_keepalive() {
+        let (port, _) = start_tls_server(vec![b"h2".to_vec()], None, ServerMode::Serve).await;
+
+        let api_stats = Arc::new(ApiStats::new());
+        let ping = PingConfig {
+            interval: Duration::from_millis(5),
+            timeout: Duration::from_secs(1),
+        };
+        let pool = ConnectionPool::connect(
+            insecure_connector(port),
+            1,
+            ReconnectConfig::default(),
+            Some(ping),
+            Arc::clone(&api_stats),
+        )
+        .await
+        .unwrap();
+
+        while api_stats.get_ping() < 3 {
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+        assert_eq!(api_stats.get_ping_timeout(), 0);
+        drop(pool);
+    }
+
+    #[tokio::test]
+    async fn test_connection_pool_keepalive_timeout() {
+        let (port, _) = start_tls_server(vec![b"h2".to_vec()], None, ServerMode::Stall).await;
+
+        let api_stats = Arc::new(ApiStats::new());
+        let ping = PingConfig {
+            interval: Duration::from_millis(5),
+            timeout: Duration::from_millis(20),
+        };
+        let pool = ConnectionPool::connect(
+            insecure_connector(port),
+            1,
+            ReconnectConfig::default(),
+            Some(ping),
+            Arc::clone(&api_stats),
+        )
+        .await
+        .unwrap();
+
+        // Unanswered PING closes the connection
+        while api_stats.get_ping_timeout() == 0 {
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+        assert!(pool.connections[0].is_closed());
+        assert_eq!(api_stats.get_ping(), 0);
+    }
+
+    #[test]
+    fn test_reconnect_backoff() {
+        let policy = ReconnectConfig {
+            max_attempts: 10,
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_secs(1),
+            jitter: 0.0,
+        };
+        assert_eq!(backoff(&policy, 0), Duration::from_millis(100));
+        assert_eq!(backoff(&policy, 1), Duration::from_millis(200));
+        assert_eq!(backoff(&policy, 3), Duration::from_millis(800));
+        assert_eq!(backoff(&policy, 4), Duration::from_secs(1));
+        assert_eq!(backoff(&policy, 40), Duration::from_secs(1));
+
+        let policy = ReconnectConfig {
+            jitter: 0.5,
+            ..policy
+        };
+        let delay = backoff(&policy, 1);
+        assert!(delay >= Duration::from_millis(100) && delay <= Duration::from_millis(300));
+    }
+
+    #[test]
+    fn test_http2_builder_validation() {
+        let config = Http2Config {
+            initial_window_size: Some(1 << 20),
+            initial_connection_window_size: Some(1 << 24),
+            max_concurrent_streams: Some(1000),
+            max_frame_size: Some(1 << 16),
+            max_header_list_size: Some(8192),
+            enable_push: Some(false),
+        };
+        assert!(http2_builder(&config).is_ok());
+
+        let config = Http2Config {
+            max_frame_size: Some(1024),
+            ..Default::default()
+        };
+        match http2_builder(&config) {
+            Ok(_) => panic!("Expected error"),
+            Err(err) => assert_eq!(
+                "http2.max_frame_size must be between 16384 and 16777215",
+                err.to_string()
+            ),
+        }
+
+        let config = Http2Config {
+            initial_window_size: Some(u32::MAX),
+            ..Default::default()
+        };
+        assert!(http2_builder(&config).is_err());
+    }
+}
@@ -1,14 +1,19 @@
 use std::fmt;
 
 #[derive(Debug)]
+#[allow(clippy::enum_variant_names)]
 pub enum Error {
     ScriptError(String),
+    ConnectionError(String),
+    HandshakeError(String),
 }
 
 impl fmt::Display for Error {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
             Error::ScriptError(e) => write!(f, "Script error: {}", e),
+            Error::ConnectionError(e) => write!(f, "Connection error: {}", e),
+            Error::HandshakeError(e) => write!(f, "Handshake error: {}", e),
         }
     }
 }
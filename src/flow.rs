@@ -0,0 +1,339 @@
+use crate::config;
+use crate::http_api::ErrorKind;
+use crate::scenario::NextCondition;
+use crate::scenario::Scenario;
+use crate::script::ScriptContext;
+use http::StatusCode;
+use std::error::Error;
+
+// Chain of scenarios run in order
+pub struct Flow {
+    pub name: String,
+    pub scenarios: Vec<Scenario>,
+    // Index of the flow's first scenario in the runner's scenario stats
+    pub first_stat: usize,
+    // `next` rules of each scenario, with `goto` resolved to its index
+    next: Vec<Vec<(NextCondition, NextStep)>>,
+}
+
+// `goto` jumps a chain may take before it is ended as failed
+pub const MAX_GOTO_JUMPS: u32 = 100;
+
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub enum NextStep {
+    // Repeat or go on with the following scenario, as without a rule
+    Continue,
+    Goto(usize),
+    End,
+}
+
+impl Flow {
+    pub fn new_all(configs: &[config::Flow], base_url: &str) -> Result<Vec<Flow>, Box<dyn Error>> {
+        if configs.is_empty() {
+            return Err("No flow defined".into());
+        }
+
+        let mut flows: Vec<Flow> = vec![];
+        let mut first_stat = 0;
+        for config in configs {
+            if config.scenarios.is_empty() {
+                return Err(format!("No scenario defined in flow '{}'", config.name).into());
+            }
+            if config.weight == 0 {
+                return Err(format!("Weight of flow '{}' must be at least 1", config.name).into());
+            }
+            if flows.iter().any(|flow| flow.name == config.name) {
+                return Err(format!("Duplicate flow name '{}'", config.name).into());
+            }
+            let mut next = vec![];
+            for (index, scenario) in config.scenarios.iter().enumerate() {
+                if config.scenarios[..index]
+                    .iter()
+                    .any(|other| other.name == scenario.name)
+                {
+                    return Err(format!(
+                        "Duplicate scenario name '{}' in flow '{}'",
+                        scenario.name, config.name
+                    )
+                    .into());
+                }
+                if let Some(repeat) = &scenario.repeat {
+                    repeat.validate(&scenario.name)?;
+                }
+                if let Some(delay) = &scenario.delay {
+                    delay.validate(&scenario.name)?;
+                }
+                next.push(resolve_next(config, scenario)?);
+            }
+
+            flows.push(Flow {
+                name: config.name.clone(),
+                scenarios: config
+                    .scenarios
+                    .iter()
+                    .map(|scenario| Scenario::new(scenario, base_url))
+                    .collect::<Result<_, _>>()?,
+                first_stat,
+                next,
+            });
+            first_stat += config.scenarios.len();
+        }
+        Ok(flows)
+    }
+
+    // Step of the first `next` rule of the scenario matching the response
+    pub fn next_step(
+        &self,
+        scenario_id: usize,
+        status: StatusCode,
+        passed: bool,
+        ctx: &ScriptContext,
+    ) -> Option<NextStep> {
+        self.next[scenario_id]
+            .iter()
+            .find(|(when, _)| when.matches(status, passed, ctx))
+            .map(|(_, step)| *step)
+    }
+
+    // Step after a request that failed without a response
+    pub fn next_error_step(
+        &self,
+        scenario_id: usize,
+        kind: ErrorKind,
+        ctx: &ScriptContext,
+    ) -> Option<NextStep> {
+        self.next[scenario_id]
+            .iter()
+            .find(|(when, _)| when.matches_error(kind, ctx))
+            .map(|(_, step)| *step)
+    }
+}
+
+fn resolve_next(
+    flow: &config::Flow,
+    scenario: &config::Scenario,
+) -> Result<Vec<(NextCondition, NextStep)>, Box<dyn Error>> {
+    let mut next = vec![];
+    for rule in &scenario.next {
+        let step = match (&rule.goto, rule.end) {
+            (Some(_), true) => {
+                return Err(format!(
+                    "Next rule of scenario '{}' can either goto or end, not both",
+                    scenario.name
+                )
+                .into())
+            }
+            (Some(goto), false) => {
+                let id = flow
+                    .scenarios
+                    .iter()
+                    .position(|s| &s.name == goto)
+                    .ok_or_else(|| {
+                        format!(
+                            "Next rule of scenario '{}' goes to '{}', not found in flow '{}'",
+                            scenario.name, goto, flow.name
+                        )
+                    })?;
+                NextStep::Goto(id)
+            }
+            (None, true) => NextStep::End,
+            (None, false) => NextStep::Continue,
+        };
+        next.push((rule.when.clone(), step));
+    }
+    Ok(next)
+}
+
+// Average requests of one chain of the flow
+fn expected_requests(flow: &config::Flow) -> f64 {
+    flow.scenarios
+        .iter()
+        .map(|scenario| {
+            scenario
+                .repeat
+                .as_ref()
+                .map_or(1.0, |repeat| repeat.expected_requests())
+        })
+        .sum()
+}
+
+// Picks the flow of each new chain. Weights are shares of the requests, so a
+// flow's chains are picked in proportion to its weight over its length,
+// interleaved by smooth weighted round robin.
+pub struct FlowMix {
+    weights: Vec<f64>,
+    current: Vec<f64>,
+    requests_per_chain: f64,
+}
+
+impl FlowMix {
+    pub fn new(configs: &[config::Flow]) -> FlowMix {
+        let weights: Vec<f64> = configs
+            .iter()
+            .map(|flow| flow.weight as f64 / expected_requests(flow))
+            .collect();
+        let total_weight: f64 = configs.iter().map(|flow| flow.weight as f64).sum();
+        let requests_per_chain = total_weight / weights.iter().sum::<f64>();
+
+        FlowMix {
+            current: vec![0.0; weights.len()],
+            weights,
+            requests_per_chain,
+        }
+    }
+
+    // Average number of requests of a chain
+    pub fn requests_per_chain(&self) -> f64 {
+        self.requests_per_chain
+    }
+
+    pub fn next(&mut self) -> usize {
+        let total: f64 = self.weights.iter().sum();
+        let mut picked = 0;
+        for (i, weight) in self.weights.iter().enumerate() {
+            self.current[i] += weight;
+            if self.current[i] > self.current[picked] {
+                picked = i;
+            }
+        }
+        self.current[picked] -= total;
+        picked
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scenario::Global;
+    use std::collections::HashMap;
+    use std::sync::Arc;
+    use std::sync::RwLock;
+
+    fn flow(name: &str, weight: u32, scenarios: usize) -> config::Flow {
+        let scenario: config::Scenario = serde_yaml::from_str(
+            r#"
+            name: get
+            request:
+              method: GET
+              path: "/"
+              timeout: 1s
+            response:
+              assert:
+                status: 200
+            "#,
+        )
+        .unwrap();
+        config::Flow {
+            name: name.into(),
+            weight,
+            scenarios: (0..scenarios)
+                .map(|i| config::Scenario {
+                    name: format!("get{}", i),
+                    ..scenario.clone()
+                })
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn test_flow_mix_request_shares() {
+        // 70% of the requests in 3-step chains, 20% and 10% in single requests
+        let configs = [
+            flow("provision", 70, 3),
+            flow("query", 20, 1),
+            flow("abandon", 10, 1),
+        ];
+        let mut mix = FlowMix::new(&configs);
+
+        let mut requests = [0; 3];
+        for _ in 0..1600 {
+            let flow = mix.next();
+            requests[flow] += configs[flow].scenarios.len();
+        }
+        assert_eq!(requests, [2100, 600, 300]);
+        assert!((mix.requests_per_chain() - 1.875).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_flow_mix_interleaved() {
+        let mut mix = FlowMix::new(&[flow("a", 2, 1), flow("b", 1, 1)]);
+        let picked: Vec<usize> = (0..6).map(|_| mix.next()).collect();
+        assert_eq!(picked, vec![0, 1, 0, 0, 1, 0]);
+    }
+
+    #[test]
+    fn test_flow_mix_repeat() {
+        let mut provision = flow("provision", 1, 3);
+        provision.scenarios[1].repeat = serde_yaml::from_str("count: 8").unwrap();
+        let mut query = flow("query", 1, 1);
+        query.scenarios[0].repeat = serde_yaml::from_str("count: { min: 1, max: 3 }").unwrap();
+
+        // 10 and 2 requests per chain
+        let mix = FlowMix::new(&[provision, query]);
+        assert!((mix.requests_per_chain() - 2.0 / (0.1 + 0.5)).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_flow_next_step() {
+        let mut config = flow("session", 1, 3);
+        config.scenarios[0].name = "create".into();
+        config.scenarios[1].name = "update".into();
+        config.scenarios[2].name = "cleanup".into();
+        config.scenarios[1].next = serde_yaml::from_str(
+            r#"
+            - when: { status: 200 }
+            - when: { status: 404 }
+              goto: cleanup
+            - when: { assert: failed }
+              end: true
+            - when: { error: reset }
+            "#,
+        )
+        .unwrap();
+        let flows = Flow::new_all(&[config.clone()], "http://localhost").unwrap();
+
+        let global = Arc::new(RwLock::new(Global {
+            variables: HashMap::new(),
+        }));
+        let ctx = ScriptContext::new(global);
+        let next = |status, passed| flows[0].next_step(1, status, passed, &ctx);
+        assert_eq!(next(StatusCode::OK, true), Some(NextStep::Continue));
+        assert_eq!(next(StatusCode::NOT_FOUND, false), Some(NextStep::Goto(2)));
+        assert_eq!(next(StatusCode::CONFLICT, false), Some(NextStep::End));
+        assert_eq!(next(StatusCode::CONFLICT, true), None);
+        assert_eq!(flows[0].next_step(0, StatusCode::OK, true, &ctx), None);
+        assert_eq!(
+            flows[0].next_error_step(1, ErrorKind::Reset, &ctx),
+            Some(NextStep::Continue)
+        );
+        assert_eq!(flows[0].next_error_step(1, ErrorKind::Timeout, &ctx), None);
+
+        config.scenarios[1].next[1].goto = Some("release".into());
+        assert!(Flow::new_all(&[config.clone()], "http://localhost").is_err());
+        config.scenarios[1].next[1].goto = Some("cleanup".into());
+        config.scenarios[1].next[1].end = true;
+        assert!(Flow::new_all(&[config], "http://localhost").is_err());
+    }
+
+    #[test]
+    fn test_flows_stat_offsets() {
+        let flows = Flow::new_all(&[flow("a", 1, 2), flow("b", 1, 3)], "http://localhost").unwrap();
+        assert_eq!(flows[0].first_stat, 0);
+        assert_eq!(flows[1].first_stat, 2);
+
+        assert!(Flow::new_all(&[], "http://localhost").is_err());
+        assert!(Flow::new_all(&[flow("a", 0, 1)], "http://localhost").is_err());
+        assert!(Flow::new_all(&[flow("a", 1, 0)], "http://localhost").is_err());
+        assert!(Flow::new_all(&[flow("a", 1, 1), flow("a", 1, 1)], "http://localhost").is_err());
+
+        let mut duplicate = flow("a", 1, 2);
+        duplicate.scenarios[1].name = duplicate.scenarios[0].name.clone();
+        assert!(Flow::new_all(&[duplicate], "http://localhost").is_err());
+        // Names only need to be unique within a flow
+        assert!(Flow::new_all(&[flow("a", 1, 1), flow("b", 1, 1)], "http://localhost").is_ok());
+
+        let mut repeated = flow("a", 1, 1);
+        repeated.scenarios[0].repeat = serde_yaml::from_str("count: 0").unwrap();
+        assert!(Flow::new_all(&[repeated], "http://localhost").is_err());
+    }
+}
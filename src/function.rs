@@ -58,8 +58,7 @@ pub struct RandomFunction {
 impl RandomFunction {
     pub fn apply(&self) -> i32 {
         let mut rng = rand::thread_rng();
-        let value = rng.gen_range(self.min..=self.max);
-        value
+        rng.gen_range(self.min..=self.max)
     }
 }
 
@@ -69,11 +68,11 @@ pub struct NowFunction {}
 impl NowFunction {
     pub fn apply(&self, format: Option<String>) -> String {
         let now = chrono::Utc::now();
-        return if let Some(format) = format {
-            return now.format(&format).to_string();
+        if let Some(format) = format {
+            now.format(&format).to_string()
         } else {
             now.to_rfc3339()
-        };
+        }
     }
 }
 
@@ -96,7 +95,6 @@ impl CopyFunction {
 }
 
 #[cfg(test)]
-
 mod tests {
     use super::*;
 
@@ -140,6 +138,6 @@ mod tests {
     fn test_random_function() {
         let f = RandomFunction { min: 1, max: 10 };
         let value = f.apply();
-        assert!(value >= 1 && value <= 10);
+        assert!((1..=10).contains(&value));
     }
 }
@@ -1,40 +1,115 @@
 use bytes::Bytes;
+use bytes::BytesMut;
 use h2::client::ResponseFuture;
 use h2::client::SendRequest;
 use h2::SendStream;
 use http::Method;
 use http::Request;
 use http::StatusCode;
+use serde::Deserialize;
+use serde::Serialize;
 use std::collections::HashMap;
 use std::error::Error;
 use std::fmt;
 use std::time::Instant;
 use tokio::task::JoinHandle;
+use tokio::time::error::Elapsed;
 use tokio::time::timeout;
 use tokio::time::Duration;
 
+#[derive(Clone)]
 pub struct HttpRequest {
     pub uri: String,
     pub method: Method,
     pub headers: Option<Vec<HashMap<String, String>>>,
-    pub body: Option<serde_json::Value>,
+    pub body: Option<Bytes>,
     pub timeout: Duration,
 }
 
 pub struct HttpResponse {
     pub status: StatusCode,
     pub headers: http::HeaderMap,
+    // Parsed when the content type is JSON
     pub body: Option<serde_json::Value>,
+    pub raw_body: Bytes,
     pub request_start: Instant,
     pub retry_count: u8,
 }
 
 #[derive(Debug)]
-pub struct HttpError(String);
+pub enum HttpError {
+    Timeout(Duration),
+    // RST_STREAM from the server
+    Reset(h2::Reason),
+    // GOAWAY or the connection went away
+    ConnectionClosed(String),
+    // Either side broke the HTTP/2 protocol
+    Protocol(String),
+    // Not sent, max_in_flight requests were already waiting for a response
+    Dropped,
+    Failed(String),
+}
+
+// Type of a failed request, for `next` rules
+#[derive(Debug, Deserialize, Serialize, PartialEq, Copy, Clone)]
+#[serde(rename_all = "lowercase")]
+pub enum ErrorKind {
+    Timeout,
+    Reset,
+    Closed,
+    Protocol,
+    Other,
+}
+
+impl HttpError {
+    pub fn kind(&self) -> ErrorKind {
+        match self {
+            HttpError::Timeout(_) => ErrorKind::Timeout,
+            HttpError::Reset(_) => ErrorKind::Reset,
+            HttpError::ConnectionClosed(_) => ErrorKind::Closed,
+            HttpError::Protocol(_) => ErrorKind::Protocol,
+            HttpError::Dropped | HttpError::Failed(_) => ErrorKind::Other,
+        }
+    }
+
+    // Typed when it comes from h2, `Failed` with `context` otherwise
+    pub fn from_error(e: &(dyn Error + 'static), context: &str) -> HttpError {
+        match e.downcast_ref::<h2::Error>() {
+            Some(e) => HttpError::from(e),
+            None => HttpError::Failed(format!("{}: {}", context, e)),
+        }
+    }
+}
+
+impl From<&h2::Error> for HttpError {
+    fn from(e: &h2::Error) -> Self {
+        let reason = e.reason().unwrap_or(h2::Reason::NO_ERROR);
+        if e.is_reset() && e.is_remote() {
+            HttpError::Reset(reason)
+        } else if e.is_io() || (e.is_go_away() && reason == h2::Reason::NO_ERROR) {
+            HttpError::ConnectionClosed(e.to_string())
+        } else if e.is_go_away() || e.is_library() || e.is_reset() {
+            HttpError::Protocol(e.to_string())
+        } else {
+            HttpError::Failed(e.to_string())
+        }
+    }
+}
 
 impl fmt::Display for HttpError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "HttpError: {}", self.0)
+        match self {
+            HttpError::Timeout(timeout) => write!(f, "HttpError: Timed out after {:?}", timeout),
+            HttpError::Reset(reason) => {
+                write!(f, "HttpError: Stream reset by server, {:?}", reason)
+            }
+            HttpError::ConnectionClosed(reason) => {
+                write!(f, "HttpError: Connection closed, {}", reason)
+            }
+            HttpError::Protocol(reason) => write!(f, "HttpError: Protocol error, {}", reason),
+            HttpError::Dropped => write!(f, "HttpError: Dropped, max_in_flight reached"),
+            HttpError::Failed(reason) => write!(f, "HttpError: {}", reason),
+        }
     }
 }
 
@@ -71,10 +146,10 @@ pub async fn send_request(
     let (response, mut stream, retry_count, request_start) =
         send_request_with_retries(client, &request).await?;
 
-    let request_body = serde_json::to_string(&http_request.body)?;
-    log::debug!("Request body: {}", request_body);
+    let request_body = http_request.body.unwrap_or_default();
+    log::debug!("Request body: {}", String::from_utf8_lossy(&request_body));
 
-    stream.send_data(request_body.into(), true)?;
+    stream.send_data(request_body, true)?;
     // log::debug!("Request sent");
 
     let result: tokio::task::JoinHandle<Result<HttpResponse, HttpError>> =
@@ -92,37 +167,42 @@ pub async fn send_request(
 
                 // Body
                 let mut body = response.into_body();
-                let mut response_body = String::new();
-
-                loop {
-                    let chunk = timeout(http_request.timeout, body.data()).await;
-                    if let Some(chunk) = chunk? {
-                        response_body.push_str(&String::from_utf8(chunk?.clone().to_vec())?);
-                    } else {
-                        // no more data chunk, exit loop
-                        break;
-                    }
+                let mut raw_body = BytesMut::new();
+
+                while let Some(chunk) = timeout(http_request.timeout, body.data()).await? {
+                    let chunk = chunk?;
+                    // Lets the server send more than one window of body
+                    let _ = body.flow_control().release_capacity(chunk.len());
+                    raw_body.extend_from_slice(&chunk);
                 }
 
-                let body = parse_json_body(&response_body, &headers);
+                let raw_body = raw_body.freeze();
+                let body = parse_json_body(&raw_body, &headers);
 
                 Ok(HttpResponse {
                     status,
                     headers,
                     body,
+                    raw_body,
                     request_start,
                     retry_count,
                 })
             })
             .await;
 
-            result.map_err(|e| HttpError(format!("Error processing response: {}", e)))
+            result.map_err(|e| {
+                if e.is::<Elapsed>() {
+                    HttpError::Timeout(http_request.timeout)
+                } else {
+                    HttpError::from_error(e.as_ref(), "Error processing response")
+                }
+            })
         });
 
     Ok(result)
 }
 
-fn parse_json_body(response_body: &str, headers: &http::HeaderMap) -> Option<serde_json::Value> {
+fn parse_json_body(response_body: &[u8], headers: &http::HeaderMap) -> Option<serde_json::Value> {
     if response_body.is_empty() {
         return None;
     }
@@ -138,7 +218,7 @@ fn parse_json_body(response_body: &str, headers: &http::HeaderMap) -> Option<ser
         return None;
     }
 
-    match serde_json::from_str(response_body) {
+    match serde_json::from_slice(response_body) {
         Ok(body) => Some(body),
         Err(e) => {
             log::error!("Error parsing response body: {}", e);
@@ -0,0 +1,331 @@
+use crate::config::IntervalReportConfig;
+use crate::stats;
+use crate::stats::ApiStats;
+use crate::stats::LatencySummary;
+use hdrhistogram::Histogram;
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fs::File;
+use std::io::Write;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::mpsc::Receiver;
+use tokio::sync::mpsc::Sender;
+use tokio::task::JoinHandle;
+
+pub struct IntervalStats {
+    pub success: u32,
+    pub error: u32,
+    pub in_flight: u32,
+    pub rtt_histogram: Histogram<u64>,
+}
+
+impl IntervalStats {
+    pub fn new() -> IntervalStats {
+        IntervalStats {
+            success: 0,
+            error: 0,
+            in_flight: 0,
+            rtt_histogram: stats::new_histogram(),
+        }
+    }
+
+    pub fn add(&mut self, other: &IntervalStats) {
+        self.success += other.success;
+        self.error += other.error;
+        self.in_flight += other.in_flight;
+        self.rtt_histogram.add(&other.rtt_histogram).unwrap();
+    }
+}
+
+pub enum IntervalMessage {
+    // Stats of a runner for the n-th window since it started
+    Window(u32, IntervalStats),
+    // The runner stopped, no more windows will come from it
+    Finished,
+}
+
+// Runner side, samples the runner's stats at the end of every window
+#[derive(Clone)]
+pub struct IntervalSampler {
+    pub interval: Duration,
+    pub tx: Sender<IntervalMessage>,
+}
+
+impl IntervalSampler {
+    pub fn start(&self, api_stats: Arc<ApiStats>) -> JoinHandle<()> {
+        let interval = self.interval;
+        let tx = self.tx.clone();
+
+        tokio::task::spawn(async move {
+            let mut ticker = tokio::time::interval(interval);
+            ticker.tick().await;
+
+            let mut window = 0;
+            let mut last_success = 0;
+            let mut last_error = 0;
+            loop {
+                ticker.tick().await;
+                window += 1;
+
+                let success = api_stats.get_success();
+                let error = api_stats.get_error();
+                let stats = IntervalStats {
+                    success: success - last_success,
+                    error: error - last_error,
+                    in_flight: api_stats.get_in_flight(),
+                    rtt_histogram: api_stats.take_window_rtt(),
+                };
+                last_success = success;
+                last_error = error;
+
+                if tx
+                    .send(IntervalMessage::Window(window, stats))
+                    .await
+                    .is_err()
+                {
+                    break;
+                }
+            }
+        })
+    }
+}
+
+// Reporter side, merges the windows of all runners
+pub struct IntervalReporter {
+    interval: Duration,
+    running: u8,
+    windows: BTreeMap<u32, (u8, IntervalStats)>,
+    file: Option<File>,
+}
+
+impl IntervalReporter {
+    pub fn new(
+        config: &IntervalReportConfig,
+        runners: u8,
+    ) -> Result<IntervalReporter, Box<dyn Error>> {
+        if config.interval.is_zero() {
+            return Err("Interval report interval must be greater than 0".into());
+        }
+
+        let file = match &config.file {
+            Some(path) => {
+                let mut file = File::create(path)
+                    .map_err(|e| format!("Failed to create '{}': {}", path, e))?;
+                writeln!(
+                    file,
+                    "elapsed_s,rps,success,error,error_rate,in_flight,mean_ms,p50_ms,p90_ms,p95_ms,p99_ms,max_ms"
+                )?;
+                Some(file)
+            }
+            None => None,
+        };
+
+        Ok(IntervalReporter {
+            interval: config.interval,
+            running: runners,
+            windows: BTreeMap::new(),
+            file,
+        })
+    }
+
+    pub fn sampler(&self, tx: Sender<IntervalMessage>) -> IntervalSampler {
+        IntervalSampler {
+            interval: self.interval,
+            tx,
+        }
+    }
+
+    pub async fn run(mut self, mut rx: Receiver<IntervalMessage>) {
+        while let Some(message) = rx.recv().await {
+            for (window, stats) in self.handle(message) {
+                self.report(window, &stats);
+            }
+        }
+        for (window, stats) in self.flush() {
+            self.report(window, &stats);
+        }
+    }
+
+    // Returns the windows every running runner has reported, in order
+    fn handle(&mut self, message: IntervalMessage) -> Vec<(u32, IntervalStats)> {
+        match message {
+            IntervalMessage::Window(window, stats) => {
+                let (count, merged) = self
+                    .windows
+                    .entry(window)
+                    .or_insert_with(|| (0, IntervalStats::new()));
+                *count += 1;
+                merged.add(&stats);
+            }
+            IntervalMessage::Finished => {
+                self.running = self.running.saturating_sub(1);
+            }
+        }
+
+        let mut completed = vec![];
+        while let Some(entry) = self.windows.first_entry() {
+            if entry.get().0 < self.running {
+                break;
+            }
+            let window = *entry.key();
+            completed.push((window, entry.remove().1));
+        }
+        completed
+    }
+
+    fn flush(&mut self) -> Vec<(u32, IntervalStats)> {
+        std::mem::take(&mut self.windows)
+            .into_iter()
+            .map(|(window, (_, stats))| (window, stats))
+            .collect()
+    }
+
+    fn report(&mut self, window: u32, stats: &IntervalStats) {
+        let elapsed_s = (self.interval * window).as_secs_f64();
+        let rps = stats.success as f64 / self.interval.as_secs_f64();
+        let total = stats.success + stats.error;
+        let error_rate = if total == 0 {
+            0.0
+        } else {
+            stats.error as f64 / total as f64 * 100.0
+        };
+        let rtt = LatencySummary::new(&stats.rtt_histogram);
+
+        log::info!(
+            "[{:.1}s] RPS: {:.3}, Error: {:.2}% ({}/{}), In-flight: {}, p50: {:.3}ms, p90: {:.3}ms, p99: {:.3}ms, max: {:.3}ms",
+            elapsed_s,
+            rps,
+            error_rate,
+            stats.error,
+            total,
+            stats.in_flight,
+            rtt.p50,
+            rtt.p90,
+            rtt.p99,
+            rtt.max
+        );
+
+        if let Some(file) = &mut self.file {
+            let result = writeln!(
+                file,
+                "{:.3},{:.3},{},{},{:.2},{},{:.3},{:.3},{:.3},{:.3},{:.3},{:.3}",
+                elapsed_s,
+                rps,
+                stats.success,
+                stats.error,
+                error_rate,
+                stats.in_flight,
+                rtt.mean,
+                rtt.p50,
+                rtt.p90,
+                rtt.p95,
+                rtt.p99,
+                rtt.max
+            );
+            if let Err(e) = result {
+                log::error!("Failed to write interval report: {}", e);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn window_stats(success: u32, error: u32, rtt: u64) -> IntervalStats {
+        let mut stats = IntervalStats::new();
+        stats.success = success;
+        stats.error = error;
+        stats.in_flight = 1;
+        stats.rtt_histogram.record(rtt).unwrap();
+        stats
+    }
+
+    fn reporter(runners: u8) -> IntervalReporter {
+        let config = IntervalReportConfig {
+            interval: Duration::from_secs(1),
+            file: None,
+        };
+        IntervalReporter::new(&config, runners).unwrap()
+    }
+
+    #[test]
+    fn test_interval_reporter_merges_runners() {
+        let mut reporter = reporter(2);
+
+        let completed = reporter.handle(IntervalMessage::Window(1, window_stats(10, 0, 1000)));
+        assert!(completed.is_empty());
+        let completed = reporter.handle(IntervalMessage::Window(2, window_stats(5, 1, 1000)));
+        assert!(completed.is_empty());
+
+        let completed = reporter.handle(IntervalMessage::Window(1, window_stats(20, 2, 3000)));
+        assert_eq!(completed.len(), 1);
+        let (window, stats) = &completed[0];
+        assert_eq!(*window, 1);
+        assert_eq!(stats.success, 30);
+        assert_eq!(stats.error, 2);
+        assert_eq!(stats.in_flight, 2);
+        assert_eq!(stats.rtt_histogram.len(), 2);
+
+        // A finished runner no longer holds back the windows of the others
+        let completed = reporter.handle(IntervalMessage::Finished);
+        assert_eq!(completed.len(), 1);
+        assert_eq!(completed[0].0, 2);
+        assert_eq!(completed[0].1.success, 5);
+
+        let completed = reporter.handle(IntervalMessage::Window(3, window_stats(7, 0, 1000)));
+        assert_eq!(completed.len(), 1);
+        assert_eq!(completed[0].0, 3);
+        assert!(reporter.flush().is_empty());
+    }
+
+    #[test]
+    fn test_interval_reporter_flush() {
+        let mut reporter = reporter(2);
+        reporter.handle(IntervalMessage::Window(1, window_stats(10, 0, 1000)));
+        reporter.handle(IntervalMessage::Window(2, window_stats(10, 0, 1000)));
+
+        let flushed = reporter.flush();
+        let windows: Vec<u32> = flushed.iter().map(|(window, _)| *window).collect();
+        assert_eq!(windows, vec![1, 2]);
+    }
+
+    #[tokio::test]
+    async fn test_interval_sampler() {
+        let api_stats = Arc::new(ApiStats::new());
+        let (tx, mut rx) = tokio::sync::mpsc::channel(8);
+        let sampler = IntervalSampler {
+            interval: Duration::from_millis(20),
+            tx,
+        };
+
+        api_stats.inc_success();
+        api_stats.inc_rtt(1000);
+        api_stats.inc_in_flight();
+        let handle = sampler.start(Arc::clone(&api_stats));
+
+        let Some(IntervalMessage::Window(window, stats)) = rx.recv().await else {
+            panic!("expected a window");
+        };
+        assert_eq!(window, 1);
+        assert_eq!(stats.success, 1);
+        assert_eq!(stats.in_flight, 1);
+        assert_eq!(stats.rtt_histogram.len(), 1);
+
+        // Only what happened since the previous window
+        api_stats.inc_error();
+        api_stats.dec_in_flight();
+        let Some(IntervalMessage::Window(window, stats)) = rx.recv().await else {
+            panic!("expected a window");
+        };
+        assert_eq!(window, 2);
+        assert_eq!(stats.success, 0);
+        assert_eq!(stats.error, 1);
+        assert_eq!(stats.in_flight, 0);
+        assert_eq!(stats.rtt_histogram.len(), 0);
+
+        handle.abort();
+    }
+}
@@ -0,0 +1,158 @@
+use crate::config::LoadStage;
+use std::error::Error;
+use std::time::Duration;
+
+// Ramps are approximated by steps of constant rate of this length
+const RAMP_STEP: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct LoadStep {
+    pub stage: usize,
+    pub target_rps: u32,
+    pub duration: Duration,
+}
+
+// Target rate over time, as consecutive steps of constant rate
+#[derive(Debug, Clone)]
+pub struct LoadProfile {
+    steps: Vec<LoadStep>,
+}
+
+impl LoadProfile {
+    pub fn new(stages: &[LoadStage]) -> Result<LoadProfile, Box<dyn Error>> {
+        if stages.is_empty() {
+            return Err("load_profile must have at least one stage".into());
+        }
+
+        let mut steps = vec![];
+        let mut current_rps = 0;
+        for (i, stage) in stages.iter().enumerate() {
+            if stage.duration.is_zero() {
+                return Err(format!(
+                    "Duration of load_profile stage #{} must be greater than 0",
+                    i
+                )
+                .into());
+            }
+
+            if stage.ramp {
+                // Linear from the previous rate, each step runs at the rate of its middle
+                let from = current_rps as f64;
+                let to = stage.target_rps as f64;
+                let mut elapsed = Duration::ZERO;
+                while elapsed < stage.duration {
+                    let duration = RAMP_STEP.min(stage.duration - elapsed);
+                    let middle =
+                        (elapsed + duration / 2).as_secs_f64() / stage.duration.as_secs_f64();
+                    steps.push(LoadStep {
+                        stage: i,
+                        target_rps: (from + (to - from) * middle).round() as u32,
+                        duration,
+                    });
+                    elapsed += duration;
+                }
+            } else {
+                steps.push(LoadStep {
+                    stage: i,
+                    target_rps: stage.target_rps,
+                    duration: stage.duration,
+                });
+            }
+            current_rps = stage.target_rps;
+        }
+
+        Ok(LoadProfile { steps })
+    }
+
+    pub fn constant(target_rps: u32, duration: Duration) -> LoadProfile {
+        LoadProfile {
+            steps: vec![LoadStep {
+                stage: 0,
+                target_rps,
+                duration,
+            }],
+        }
+    }
+
+    pub fn steps(&self) -> &[LoadStep] {
+        &self.steps
+    }
+
+    pub fn duration(&self) -> Duration {
+        self.steps.iter().map(|step| step.duration).sum()
+    }
+
+    pub fn peak_rps(&self) -> u32 {
+        self.steps
+            .iter()
+            .map(|step| step.target_rps)
+            .max()
+            .unwrap_or(0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stage(duration_s: u64, target_rps: u32, ramp: bool) -> LoadStage {
+        LoadStage {
+            duration: Duration::from_secs(duration_s),
+            target_rps,
+            ramp,
+        }
+    }
+
+    #[test]
+    fn test_load_profile_stages() {
+        let profile = LoadProfile::new(&[
+            stage(4, 8000, true),
+            stage(600, 8000, false),
+            stage(60, 12000, false),
+            stage(2, 0, true),
+        ])
+        .unwrap();
+
+        let rates: Vec<u32> = profile.steps().iter().map(|s| s.target_rps).collect();
+        assert_eq!(rates, vec![1000, 3000, 5000, 7000, 8000, 12000, 9000, 3000]);
+
+        let stages: Vec<usize> = profile.steps().iter().map(|s| s.stage).collect();
+        assert_eq!(stages, vec![0, 0, 0, 0, 1, 2, 3, 3]);
+
+        assert_eq!(profile.steps()[4].duration, Duration::from_secs(600));
+        assert_eq!(profile.duration(), Duration::from_secs(666));
+        assert_eq!(profile.peak_rps(), 12000);
+    }
+
+    #[test]
+    fn test_load_profile_partial_ramp_step() {
+        let profile = LoadProfile::new(&[LoadStage {
+            duration: Duration::from_millis(1500),
+            target_rps: 300,
+            ramp: true,
+        }])
+        .unwrap();
+
+        assert_eq!(
+            profile.steps(),
+            &[
+                LoadStep {
+                    stage: 0,
+                    target_rps: 100,
+                    duration: Duration::from_secs(1),
+                },
+                LoadStep {
+                    stage: 0,
+                    target_rps: 250,
+                    duration: Duration::from_millis(500),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_load_profile_invalid() {
+        assert!(LoadProfile::new(&[]).is_err());
+        assert!(LoadProfile::new(&[stage(0, 100, false)]).is_err());
+    }
+}
@@ -1,24 +1,44 @@
+mod arrival;
+mod budget;
+mod capacity;
 mod config;
+mod connection;
 mod error;
+mod flow;
 mod function;
 mod http_api;
+mod interval;
+mod load_profile;
+mod metrics;
+mod report;
 mod runner;
 mod scenario;
 mod script;
 mod scripting;
 mod stats;
+mod tls;
 mod variable;
 
+use crate::budget::ErrorBudget;
 use crate::config::read_yaml_file;
+use crate::interval::IntervalMessage;
+use crate::interval::IntervalReporter;
+use crate::metrics::MetricsServer;
+use crate::report::Report;
+use crate::report::ReportFormat;
 use crate::runner::AggregatedReport;
+use crate::runner::RunReport;
 use crate::runner::Runner;
 use crate::scenario::Global;
+use crate::stats::ApiStats;
 use chrono::Local;
 use clap::Parser;
 use std::error::Error;
 use std::io::Write;
+use std::sync::Arc;
 use std::thread;
 use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
 
 #[derive(Parser, Debug)]
 #[command(version, about, long_about = None)]
@@ -26,11 +46,15 @@ struct Args {
     #[arg(short, long, default_value = "./config.yaml")]
     config: String,
 
-    #[arg(short, long, default_value_t = 1)]
+    #[arg(long, default_value_t = 1)]
     count: u8,
 
     #[arg(short, long)]
     overrides: Vec<String>,
+
+    /// Write the aggregated results to a .json or .csv file, can be repeated
+    #[arg(short, long)]
+    report: Vec<String>,
 }
 
 #[tokio::main]
@@ -40,6 +64,12 @@ pub async fn main() -> Result<(), Box<dyn Error>> {
     // Read config
     let config = read_yaml_file(&args.config, args.overrides)?;
 
+    // Fail before the run rather than after it
+    for path in &args.report {
+        ReportFormat::from_path(path)?;
+    }
+    let budget = Arc::new(ErrorBudget::new(&config.runner.assert)?);
+
     // Configure Logging
     env_logger::Builder::new()
         .filter_module("jsonpath_lib", log::LevelFilter::Error)
@@ -64,12 +94,35 @@ pub async fn main() -> Result<(), Box<dyn Error>> {
 
     log::debug!("HTTP2 Load Generator Config:\n{}", config);
 
+    // Interval reporter fed by all runners
+    let mut interval_tx = None;
+    let mut interval_reporter = None;
+    if let Some(interval_config) = &config.interval_report {
+        let reporter = IntervalReporter::new(interval_config, config.parallel)?;
+        let (tx, rx) = mpsc::channel(config.parallel.max(1) as usize * 2);
+        interval_tx = Some((reporter.sampler(tx.clone()), tx));
+        interval_reporter = Some(tokio::spawn(reporter.run(rx)));
+    }
+
+    let runner_stats: Vec<Arc<ApiStats>> = (0..config.parallel)
+        .map(|_| Arc::new(ApiStats::new()))
+        .collect();
+
+    // Live metrics of all runners
+    if let Some(metrics_config) = &config.metrics {
+        MetricsServer::bind(&metrics_config.listen, runner_stats.clone())
+            .await?
+            .start();
+    }
+
     // Runner in parallel
-    let (tx, mut rx) = mpsc::channel(8);
-    for _ in 0..config.parallel {
-        let tx = tx.clone();
+    let started_at = Local::now();
+    let mut runners = vec![];
+    for api_stats in runner_stats {
         let config = config.clone();
-        tokio::task::spawn_blocking(move || {
+        let interval_tx = interval_tx.clone();
+        let budget = Arc::clone(&budget);
+        runners.push(tokio::task::spawn_blocking(move || {
             let rt = tokio::runtime::Builder::new_current_thread()
                 .enable_all()
                 .build()
@@ -77,21 +130,83 @@ pub async fn main() -> Result<(), Box<dyn Error>> {
 
             rt.block_on(async move {
                 let global = Global::new(config.runner.global.clone());
-                let mut runner = Runner::new(config.runner).unwrap();
-                let report = runner.run(global).await.unwrap();
-                tx.send(report).await.unwrap();
-            });
-        });
+                let sampler = interval_tx.as_ref().map(|(sampler, _)| sampler.clone());
+                let result = match Runner::new(config.runner) {
+                    Ok(mut runner) => runner.run(global, api_stats, sampler, budget).await,
+                    Err(e) => Err(e),
+                };
+                if let Some((_, interval_tx)) = interval_tx {
+                    let _ = interval_tx.send(IntervalMessage::Finished).await;
+                }
+                result.map_err(|e| e.to_string())
+            })
+        }));
     }
 
-    drop(tx);
+    drop(interval_tx);
 
     // Aggregate report
-    let mut aggregate_report = AggregatedReport::new();
-    while let Some(report) = rx.recv().await {
-        aggregate_report.add(report);
+    let (mut aggregate_report, failed_runners) = collect_reports(runners).await;
+
+    // Print the last windows before the final report
+    if let Some(interval_reporter) = interval_reporter {
+        interval_reporter.await?;
+    }
+
+    if failed_runners == config.parallel {
+        return Err("All runners failed".into());
     }
+    aggregate_report.aborted = budget.exceeded().map(String::from);
     aggregate_report.report();
 
+    let report = Report::new(&config, &aggregate_report, started_at);
+    for path in &args.report {
+        report.write(path)?;
+        log::info!("Report written to {}", path);
+    }
+
+    if let Some(reason) = budget.exceeded() {
+        return Err(format!("Run aborted, error budget exceeded: {}", reason).into());
+    }
+    if failed_runners > 0 {
+        return Err(format!("{} of {} runners failed", failed_runners, config.parallel).into());
+    }
     Ok(())
 }
+
+// Waits for all runners, one that failed or panicked is logged and counted
+async fn collect_reports(
+    runners: Vec<JoinHandle<Result<RunReport, String>>>,
+) -> (AggregatedReport, u8) {
+    let mut aggregate_report = AggregatedReport::new();
+    let mut failed_runners = 0;
+    for runner in runners {
+        let e = match runner.await {
+            Ok(Ok(report)) => {
+                aggregate_report.add(report);
+                continue;
+            }
+            Ok(Err(e)) => e,
+            Err(e) => e.to_string(),
+        };
+        log::error!("Runner failed: {}", e);
+        failed_runners += 1;
+    }
+    (aggregate_report, failed_runners)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_collect_reports_failed_runners() {
+        let runners = vec![
+            tokio::task::spawn_blocking(|| Err("Connection refused".to_string())),
+            tokio::task::spawn_blocking(|| panic!("Runner panicked")),
+        ];
+        let (report, failed_runners) = collect_reports(runners).await;
+        assert_eq!(failed_runners, 2);
+        assert_eq!(report.total_success + report.total_error, 0);
+    }
+}
@@ -0,0 +1,420 @@
+use crate::stats;
+use crate::stats::ApiStats;
+use crate::stats::ScenarioStats;
+use hdrhistogram::Histogram;
+use std::error::Error;
+use std::fmt::Write;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::AsyncReadExt;
+use tokio::io::AsyncWriteExt;
+use tokio::net::TcpListener;
+use tokio::net::TcpStream;
+
+// A client sending its request slower than this is disconnected
+const READ_TIMEOUT: Duration = Duration::from_secs(5);
+
+// Upper bounds in seconds of the latency histogram buckets
+const LATENCY_BUCKETS: &[f64] = &[
+    0.0005, 0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+
+// Name, help and value of the counters labelled by scenario
+type ScenarioMetric = (&'static str, &'static str, fn(&ScenarioStats) -> u32);
+
+const SCENARIO_COUNTERS: &[ScenarioMetric] = &[
+    (
+        "h2load_success_total",
+        "Requests that passed all assertions",
+        |s| s.success,
+    ),
+    (
+        "h2load_errors_total",
+        "Requests that failed or did not pass the assertions",
+        |s| s.error,
+    ),
+    (
+        "h2load_timeouts_total",
+        "Requests that timed out waiting for the response",
+        |s| s.timeout,
+    ),
+    (
+        "h2load_resets_total",
+        "Requests reset by the server with RST_STREAM",
+        |s| s.reset,
+    ),
+    (
+        "h2load_connection_closed_total",
+        "Requests lost with their connection",
+        |s| s.connection_closed,
+    ),
+    (
+        "h2load_protocol_errors_total",
+        "Requests failed on an HTTP/2 protocol error",
+        |s| s.protocol_error,
+    ),
+    ("h2load_retries_total", "Retries to open a stream", |s| {
+        s.retry
+    }),
+];
+
+// Name, type, help and value of the metrics summed over all runners
+type RunnerMetric = (
+    &'static str,
+    &'static str,
+    &'static str,
+    fn(&ApiStats) -> u32,
+);
+
+const RUNNER_METRICS: &[RunnerMetric] = &[
+    (
+        "h2load_in_flight_requests",
+        "gauge",
+        "Requests sent and waiting for a response",
+        ApiStats::get_in_flight,
+    ),
+    (
+        "h2load_open_connections",
+        "gauge",
+        "HTTP/2 connections currently open",
+        ApiStats::get_open_connections,
+    ),
+    (
+        "h2load_reconnects_total",
+        "counter",
+        "Connections re-established after being closed",
+        ApiStats::get_reconnect,
+    ),
+    (
+        "h2load_pings_total",
+        "counter",
+        "PING frames acknowledged by the server",
+        ApiStats::get_ping,
+    ),
+    (
+        "h2load_ping_timeouts_total",
+        "counter",
+        "PING frames left unanswered",
+        ApiStats::get_ping_timeout,
+    ),
+    (
+        "h2load_delayed_sends_total",
+        "counter",
+        "Sends held back by max_in_flight or the server's MAX_CONCURRENT_STREAMS",
+        ApiStats::get_delayed,
+    ),
+    (
+        "h2load_dropped_sends_total",
+        "counter",
+        "Sends dropped because max_in_flight was reached",
+        ApiStats::get_dropped,
+    ),
+];
+
+// Serves the stats of all runners in the Prometheus text format on `/metrics`
+pub struct MetricsServer {
+    listener: TcpListener,
+    runners: Vec<Arc<ApiStats>>,
+}
+
+impl MetricsServer {
+    pub async fn bind(
+        address: &str,
+        runners: Vec<Arc<ApiStats>>,
+    ) -> Result<MetricsServer, Box<dyn Error>> {
+        let listener = TcpListener::bind(address)
+            .await
+            .map_err(|e| format!("Failed to listen for metrics on '{}': {}", address, e))?;
+        log::info!(
+            "Serving metrics on http://{}/metrics",
+            listener.local_addr()?
+        );
+        Ok(MetricsServer { listener, runners })
+    }
+
+    pub fn start(self) {
+        let runners = Arc::new(self.runners);
+        tokio::spawn(async move {
+            loop {
+                let stream = match self.listener.accept().await {
+                    Ok((stream, _)) => stream,
+                    Err(e) => {
+                        log::warn!("Failed to accept metrics connection: {}", e);
+                        continue;
+                    }
+                };
+                let runners = Arc::clone(&runners);
+                tokio::spawn(async move {
+                    if let Err(e) = serve(stream, &runners).await {
+                        log::debug!("Metrics connection failed: {}", e);
+                    }
+                });
+            }
+        });
+    }
+}
+
+// Minimal HTTP/1.1, one request per connection
+async fn serve(mut stream: TcpStream, runners: &[Arc<ApiStats>]) -> Result<(), Box<dyn Error>> {
+    let request = tokio::time::timeout(READ_TIMEOUT, read_request(&mut stream))
+        .await
+        .map_err(|_| "Timed out reading the request")??;
+
+    let request = String::from_utf8_lossy(&request);
+    let request_line = request.lines().next().unwrap_or_default();
+    let mut parts = request_line.split(' ');
+    let (method, path) = (parts.next(), parts.next());
+
+    let (status, body) = match (method, path) {
+        (Some("GET"), Some("/metrics")) => ("200 OK", render(runners)),
+        _ => ("404 Not Found", "Not Found\n".to_string()),
+    };
+    let response = format!(
+        "HTTP/1.1 {}\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    );
+    stream.write_all(response.as_bytes()).await?;
+    stream.shutdown().await?;
+    Ok(())
+}
+
+async fn read_request(stream: &mut TcpStream) -> Result<Vec<u8>, Box<dyn Error>> {
+    let mut request = vec![];
+    let mut buf = [0; 1024];
+    while !request.windows(4).any(|w| w == b"\r\n\r\n") {
+        let n = stream.read(&mut buf).await?;
+        if n == 0 || request.len() + n > 8192 {
+            return Err("Incomplete request".into());
+        }
+        request.extend_from_slice(&buf[..n]);
+    }
+    Ok(request)
+}
+
+pub fn render(runners: &[Arc<ApiStats>]) -> String {
+    let mut scenarios: Vec<ScenarioStats> = vec![];
+    for runner in runners {
+        stats::merge_scenario_stats(&mut scenarios, &runner.get_scenarios());
+    }
+
+    let mut out = String::new();
+
+    header(
+        &mut out,
+        "h2load_requests_total",
+        "counter",
+        "Responses received by scenario and status code",
+    );
+    for scenario in &scenarios {
+        for (status, count) in &scenario.status_codes {
+            writeln!(
+                out,
+                "h2load_requests_total{{scenario=\"{}\",status=\"{}\"}} {}",
+                label(&scenario.name),
+                status,
+                count
+            )
+            .unwrap();
+        }
+    }
+
+    for (metric, help, value) in SCENARIO_COUNTERS {
+        header(&mut out, metric, "counter", help);
+        for scenario in &scenarios {
+            let name = label(&scenario.name);
+            writeln!(
+                out,
+                "{}{{scenario=\"{}\"}} {}",
+                metric,
+                name,
+                value(scenario)
+            )
+            .unwrap();
+        }
+    }
+
+    histogram(
+        &mut out,
+        "h2load_request_duration_seconds",
+        "Round trip of successful requests",
+        &scenarios,
+        |s| &s.rtt_histogram,
+    );
+    histogram(
+        &mut out,
+        "h2load_response_time_seconds",
+        "Time from when successful requests were due to their response",
+        &scenarios,
+        |s| &s.response_time_histogram,
+    );
+
+    for (metric, kind, help, value) in RUNNER_METRICS {
+        header(&mut out, metric, kind, help);
+        let total: u64 = runners.iter().map(|r| value(r) as u64).sum();
+        writeln!(out, "{} {}", metric, total).unwrap();
+    }
+
+    out
+}
+
+fn histogram(
+    out: &mut String,
+    metric: &str,
+    help: &str,
+    scenarios: &[ScenarioStats],
+    histogram: fn(&ScenarioStats) -> &Histogram<u64>,
+) {
+    header(out, metric, "histogram", help);
+    for scenario in scenarios {
+        let name = label(&scenario.name);
+        let histogram = histogram(scenario);
+        for bound in LATENCY_BUCKETS {
+            let count = histogram.count_between(0, (bound * 1_000_000.0) as u64);
+            writeln!(
+                out,
+                "{}_bucket{{scenario=\"{}\",le=\"{}\"}} {}",
+                metric, name, bound, count
+            )
+            .unwrap();
+        }
+        writeln!(
+            out,
+            "{}_bucket{{scenario=\"{}\",le=\"+Inf\"}} {}",
+            metric,
+            name,
+            histogram.len()
+        )
+        .unwrap();
+        let sum_s = histogram.mean() * histogram.len() as f64 / 1_000_000.0;
+        writeln!(out, "{}_sum{{scenario=\"{}\"}} {}", metric, name, sum_s).unwrap();
+        writeln!(
+            out,
+            "{}_count{{scenario=\"{}\"}} {}",
+            metric,
+            name,
+            histogram.len()
+        )
+        .unwrap();
+    }
+}
+
+fn header(out: &mut String, name: &str, kind: &str, help: &str) {
+    writeln!(out, "# HELP {} {}", name, help).unwrap();
+    writeln!(out, "# TYPE {} {}", name, kind).unwrap();
+}
+
+fn label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn runner_stats() -> Arc<ApiStats> {
+        let api_stats = Arc::new(ApiStats::new());
+        api_stats.set_scenarios(&["create", "update"]);
+        api_stats.update_scenario(0, |stats| {
+            stats.success += 1;
+            stats.inc_status(201);
+            stats.rtt_histogram.record(2_000).unwrap();
+            stats.response_time_histogram.record(30_000).unwrap();
+        });
+        api_stats.update_scenario(1, |stats| {
+            stats.error += 2;
+            stats.timeout += 1;
+            stats.reset += 1;
+        });
+        api_stats.inc_in_flight();
+        api_stats.inc_reconnect();
+        api_stats.inc_dropped();
+        api_stats
+    }
+
+    #[test]
+    fn test_render_metrics() {
+        let metrics = render(&[runner_stats(), runner_stats()]);
+
+        assert!(metrics.contains("# TYPE h2load_requests_total counter\n"));
+        assert!(metrics.contains("h2load_requests_total{scenario=\"create\",status=\"201\"} 2\n"));
+        assert!(metrics.contains("h2load_success_total{scenario=\"create\"} 2\n"));
+        assert!(metrics.contains("h2load_errors_total{scenario=\"update\"} 4\n"));
+        assert!(metrics.contains("h2load_timeouts_total{scenario=\"update\"} 2\n"));
+        assert!(metrics.contains("h2load_resets_total{scenario=\"update\"} 2\n"));
+        assert!(metrics.contains(
+            "h2load_request_duration_seconds_bucket{scenario=\"create\",le=\"0.001\"} 0\n"
+        ));
+        assert!(metrics.contains(
+            "h2load_request_duration_seconds_bucket{scenario=\"create\",le=\"0.0025\"} 2\n"
+        ));
+        assert!(metrics.contains(
+            "h2load_request_duration_seconds_bucket{scenario=\"create\",le=\"+Inf\"} 2\n"
+        ));
+        assert!(metrics.contains("h2load_request_duration_seconds_count{scenario=\"create\"} 2\n"));
+        assert!(metrics
+            .contains("h2load_response_time_seconds_bucket{scenario=\"create\",le=\"0.025\"} 0\n"));
+        assert!(metrics
+            .contains("h2load_response_time_seconds_bucket{scenario=\"create\",le=\"0.05\"} 2\n"));
+        assert!(metrics.contains("h2load_in_flight_requests 2\n"));
+        assert!(metrics.contains("h2load_open_connections 0\n"));
+        assert!(metrics.contains("h2load_reconnects_total 2\n"));
+        assert!(metrics.contains("h2load_delayed_sends_total 0\n"));
+        assert!(metrics.contains("h2load_dropped_sends_total 2\n"));
+    }
+
+    #[test]
+    fn test_label_escape() {
+        assert_eq!(label("a\"b\\c\nd"), "a\\\"b\\\\c\\nd");
+    }
+
+    #[tokio::test]
+    async fn test_metrics_server() {
+        let server = MetricsServer::bind("127.0.0.1:0", vec![runner_stats()])
+            .await
+            .unwrap();
+        let address = server.listener.local_addr().unwrap();
+        server.start();
+
+        let get = |path: &'static str| async move {
+            let mut stream = TcpStream::connect(address).await.unwrap();
+            let request = format!("GET {} HTTP/1.1\r\nHost: localhost\r\n\r\n", path);
+            stream.write_all(request.as_bytes()).await.unwrap();
+            let mut response = String::new();
+            stream.read_to_string(&mut response).await.unwrap();
+            response
+        };
+
+        let response = get("/metrics").await;
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.contains("h2load_success_total{scenario=\"create\"} 1\n"));
+
+        let response = get("/").await;
+        assert!(response.starts_with("HTTP/1.1 404 Not Found\r\n"));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_metrics_server_read_timeout() {
+        let server = MetricsServer::bind("127.0.0.1:0", vec![runner_stats()])
+            .await
+            .unwrap();
+        let address = server.listener.local_addr().unwrap();
+        server.start();
+
+        // Request never completed, the server closes the connection
+        let mut stream = TcpStream::connect(address).await.unwrap();
+        stream
+            .write_all(b"GET /metrics HTTP/1.1\r\n")
+            .await
+            .unwrap();
+        let start = tokio::time::Instant::now();
+        let mut response = vec![];
+        stream.read_to_end(&mut response).await.unwrap();
+        assert!(response.is_empty());
+        assert!(start.elapsed() >= READ_TIMEOUT);
+    }
+}
@@ -0,0 +1,440 @@
+use crate::config::BatchSize;
+use crate::config::Config;
+use crate::config::RunMode;
+use crate::load_profile::LoadProfile;
+use crate::runner::AggregatedReport;
+use crate::runner::TOP_FAILURES;
+use crate::stats::LatencySummary;
+use crate::stats::ScenarioStats;
+use chrono::DateTime;
+use chrono::Local;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fmt::Write;
+
+pub enum ReportFormat {
+    Json,
+    Csv,
+}
+
+impl ReportFormat {
+    // Format is picked from the file extension
+    pub fn from_path(path: &str) -> Result<ReportFormat, Box<dyn Error>> {
+        let extension = path.rsplit_once('.').map(|(_, ext)| ext.to_lowercase());
+        match extension.as_deref() {
+            Some("json") => Ok(ReportFormat::Json),
+            Some("csv") => Ok(ReportFormat::Csv),
+            _ => Err(format!("Unknown report format '{}', expected .json or .csv", path).into()),
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct Report {
+    pub config: ConfigSummary,
+    // Highest stable rate found by the capacity search
+    pub capacity_rps: Option<u32>,
+    // Sends held back or dropped by the generator, see max_in_flight
+    pub delayed_sends: u32,
+    pub dropped_sends: u32,
+    // Reason the run was aborted by the error budget
+    pub aborted: Option<String>,
+    pub timing: Timing,
+    pub total: ScenarioSummary,
+    pub scenarios: Vec<ScenarioSummary>,
+}
+
+#[derive(Serialize)]
+pub struct ConfigSummary {
+    pub base_url: String,
+    pub mode: RunMode,
+    pub parallel: u8,
+    pub target_rps: u32,
+    pub duration_s: u64,
+    pub batch_size: Option<u32>,
+    pub connections_per_runner: u16,
+    // Per runner, only in concurrency mode
+    pub virtual_users: Option<u32>,
+    pub scenarios: Vec<String>,
+}
+
+#[derive(Serialize)]
+pub struct Timing {
+    pub started_at: String,
+    pub finished_at: String,
+    pub elapsed_s: f64,
+}
+
+#[derive(Serialize)]
+pub struct ScenarioSummary {
+    pub name: String,
+    pub success: u32,
+    pub error: u32,
+    pub timeout: u32,
+    pub reset: u32,
+    pub connection_closed: u32,
+    pub protocol_error: u32,
+    pub retry: u32,
+    pub success_rate: f64,
+    pub rps: f64,
+    // Service time, from the stream being opened
+    pub latency_ms: LatencySummary,
+    // From when the request was due, corrected for coordinated omission
+    pub response_time_ms: LatencySummary,
+    pub status_codes: BTreeMap<u16, u32>,
+    // Most frequent first
+    pub failures: Vec<FailureCount>,
+}
+
+#[derive(Serialize)]
+pub struct FailureCount {
+    pub reason: String,
+    pub count: u32,
+}
+
+impl ScenarioSummary {
+    fn new(stats: &ScenarioStats, elapsed_s: f64) -> ScenarioSummary {
+        let total = stats.success + stats.error;
+        let success_rate = if total == 0 {
+            0.0
+        } else {
+            stats.success as f64 / total as f64 * 100.0
+        };
+        let rps = if elapsed_s > 0.0 {
+            stats.success as f64 / elapsed_s
+        } else {
+            0.0
+        };
+        ScenarioSummary {
+            name: stats.name.clone(),
+            success: stats.success,
+            error: stats.error,
+            timeout: stats.timeout,
+            reset: stats.reset,
+            connection_closed: stats.connection_closed,
+            protocol_error: stats.protocol_error,
+            retry: stats.retry,
+            success_rate,
+            rps,
+            latency_ms: LatencySummary::new(&stats.rtt_histogram),
+            response_time_ms: LatencySummary::new(&stats.response_time_histogram),
+            status_codes: stats.status_codes.clone(),
+            failures: stats
+                .top_failures(TOP_FAILURES)
+                .into_iter()
+                .map(|(reason, count)| FailureCount {
+                    reason: reason.into(),
+                    count,
+                })
+                .collect(),
+        }
+    }
+}
+
+impl Report {
+    pub fn new(
+        config: &Config,
+        aggregated: &AggregatedReport,
+        started_at: DateTime<Local>,
+    ) -> Report {
+        let elapsed_s = aggregated.elapsed.as_secs_f64();
+
+        let batch_size = match config.runner.batch_size {
+            BatchSize::Auto(_) => None,
+            BatchSize::Fixed(size) => Some(size),
+        };
+
+        // Peak rate and total duration when a load profile is used
+        let load_profile = match &config.runner.load_profile {
+            Some(stages) => LoadProfile::new(stages).ok(),
+            None => None,
+        };
+        let (target_rps, duration) = match &load_profile {
+            Some(profile) => (profile.peak_rps(), profile.duration()),
+            None => (config.runner.target_rps, config.runner.duration),
+        };
+
+        // Totals across all scenarios, counts come from the runners' overall stats
+        let mut total = ScenarioStats::new("total");
+        for scenario in &aggregated.scenario_stats {
+            total.add(scenario);
+        }
+        total.success = aggregated.total_success;
+        total.error = aggregated.total_error;
+
+        Report {
+            capacity_rps: aggregated.capacity_rps,
+            delayed_sends: aggregated.total_delayed,
+            dropped_sends: aggregated.total_dropped,
+            aborted: aggregated.aborted.clone(),
+            config: ConfigSummary {
+                base_url: config.runner.base_url.clone(),
+                mode: config.runner.mode,
+                parallel: config.parallel,
+                target_rps,
+                duration_s: duration.as_secs(),
+                batch_size,
+                connections_per_runner: config.runner.connections_per_runner,
+                virtual_users: match config.runner.mode {
+                    RunMode::Rate => None,
+                    RunMode::Concurrency => config.runner.concurrency.as_ref().map(|c| c.users),
+                },
+                scenarios: config.runner.scenario_names(),
+            },
+            timing: Timing {
+                started_at: started_at.to_rfc3339(),
+                finished_at: Local::now().to_rfc3339(),
+                elapsed_s,
+            },
+            total: ScenarioSummary::new(&total, elapsed_s),
+            scenarios: aggregated
+                .scenario_stats
+                .iter()
+                .map(|s| ScenarioSummary::new(s, elapsed_s))
+                .collect(),
+        }
+    }
+
+    pub fn to_json(&self) -> Result<String, Box<dyn Error>> {
+        Ok(serde_json::to_string_pretty(self)?)
+    }
+
+    // Config and timing as `# key,value` lines, then one row per scenario
+    // followed by the total, latency in ms
+    pub fn to_csv(&self) -> String {
+        let mut csv = String::new();
+        let config = &self.config;
+        let optional = |value: Option<u32>| value.map(|v| v.to_string()).unwrap_or_default();
+        let summary = [
+            ("base_url", config.base_url.clone()),
+            ("mode", format!("{:?}", config.mode).to_lowercase()),
+            ("parallel", config.parallel.to_string()),
+            ("target_rps", config.target_rps.to_string()),
+            ("duration_s", config.duration_s.to_string()),
+            ("batch_size", optional(config.batch_size)),
+            (
+                "connections_per_runner",
+                config.connections_per_runner.to_string(),
+            ),
+            ("virtual_users", optional(config.virtual_users)),
+            ("scenarios", config.scenarios.join(" ")),
+            ("capacity_rps", optional(self.capacity_rps)),
+            ("delayed_sends", self.delayed_sends.to_string()),
+            ("dropped_sends", self.dropped_sends.to_string()),
+            ("aborted", self.aborted.clone().unwrap_or_default()),
+            ("started_at", self.timing.started_at.clone()),
+            ("finished_at", self.timing.finished_at.clone()),
+            ("elapsed_s", format!("{:.3}", self.timing.elapsed_s)),
+        ];
+        for (key, value) in summary {
+            writeln!(csv, "# {},{}", key, csv_field(&value)).unwrap();
+        }
+
+        csv.push_str(
+            "scenario,success,error,timeout,reset,connection_closed,protocol_error,retry,\
+             success_rate,rps,mean_ms,p50_ms,p90_ms,p95_ms,p99_ms,p999_ms,max_ms,\
+             corrected_mean_ms,corrected_p50_ms,corrected_p90_ms,corrected_p95_ms,\
+             corrected_p99_ms,corrected_p999_ms,corrected_max_ms,status_codes,failures\n",
+        );
+        for scenario in self.scenarios.iter().chain(std::iter::once(&self.total)) {
+            let status_codes: Vec<String> = scenario
+                .status_codes
+                .iter()
+                .map(|(status, count)| format!("{}:{}", status, count))
+                .collect();
+            let failures: Vec<String> = scenario
+                .failures
+                .iter()
+                .map(|failure| format!("{} ({})", failure.reason, failure.count))
+                .collect();
+            let rtt = &scenario.latency_ms;
+            let corrected = &scenario.response_time_ms;
+            writeln!(
+                csv,
+                "{},{},{},{},{},{},{},{},{:.2},{:.3},{:.3},{:.3},{:.3},{:.3},{:.3},{:.3},{:.3},\
+                 {:.3},{:.3},{:.3},{:.3},{:.3},{:.3},{:.3},{},{}",
+                csv_field(&scenario.name),
+                scenario.success,
+                scenario.error,
+                scenario.timeout,
+                scenario.reset,
+                scenario.connection_closed,
+                scenario.protocol_error,
+                scenario.retry,
+                scenario.success_rate,
+                scenario.rps,
+                rtt.mean,
+                rtt.p50,
+                rtt.p90,
+                rtt.p95,
+                rtt.p99,
+                rtt.p999,
+                rtt.max,
+                corrected.mean,
+                corrected.p50,
+                corrected.p90,
+                corrected.p95,
+                corrected.p99,
+                corrected.p999,
+                corrected.max,
+                status_codes.join(" "),
+                csv_field(&failures.join("; "))
+            )
+            .unwrap();
+        }
+        csv
+    }
+
+    pub fn write(&self, path: &str) -> Result<(), Box<dyn Error>> {
+        let content = match ReportFormat::from_path(path)? {
+            ReportFormat::Json => self.to_json()?,
+            ReportFormat::Csv => self.to_csv(),
+        };
+        std::fs::write(path, content)
+            .map_err(|e| format!("Failed to write report '{}': {}", path, e))?;
+        Ok(())
+    }
+}
+
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn config() -> Config {
+        let yaml_str = r#"
+        log_level: "Info"
+        parallel: 2
+        runner:
+          target_rps: 100
+          duration: 10s
+          batch_size: 5
+          base_url: "http://localhost:8080/"
+          global:
+            variables: []
+          scenarios:
+            - name: create
+              request:
+                method: POST
+                path: "/create"
+                timeout: 3s
+              response:
+                assert:
+                  status: 201
+    "#;
+        serde_yaml::from_str(yaml_str).unwrap()
+    }
+
+    fn aggregated_report() -> AggregatedReport {
+        let mut create = ScenarioStats::new("create");
+        create.success = 2;
+        create.retry = 1;
+        create.rtt_histogram.record(1000).unwrap();
+        create.rtt_histogram.record(3000).unwrap();
+        create.response_time_histogram.record(1000).unwrap();
+        create.response_time_histogram.record(9000).unwrap();
+        create.inc_status(201);
+        create.inc_status(201);
+
+        let mut update = ScenarioStats::new("update, v2");
+        update.success = 1;
+        update.error = 1;
+        update.timeout = 1;
+        update.rtt_histogram.record(2000).unwrap();
+        update.inc_status(200);
+        update.inc_failure("Expected status code: 200, got: 500");
+
+        let mut aggregated = AggregatedReport::new();
+        aggregated.elapsed = Duration::from_secs(2);
+        aggregated.total_success = 3;
+        aggregated.total_error = 1;
+        aggregated.total_delayed = 4;
+        aggregated.scenario_stats = vec![create, update];
+        aggregated
+    }
+
+    #[test]
+    fn test_report_format() {
+        assert!(matches!(
+            ReportFormat::from_path("out/report.json").unwrap(),
+            ReportFormat::Json
+        ));
+        assert!(matches!(
+            ReportFormat::from_path("report.CSV").unwrap(),
+            ReportFormat::Csv
+        ));
+        assert!(ReportFormat::from_path("report.txt").is_err());
+        assert!(ReportFormat::from_path("report").is_err());
+    }
+
+    #[test]
+    fn test_report_json() {
+        let report = Report::new(&config(), &aggregated_report(), Local::now());
+        let json: serde_json::Value = serde_json::from_str(&report.to_json().unwrap()).unwrap();
+
+        assert_eq!(json["config"]["mode"], "rate");
+        assert_eq!(json["config"]["parallel"], 2);
+        assert_eq!(json["config"]["target_rps"], 100);
+        assert_eq!(json["config"]["batch_size"], 5);
+        assert_eq!(json["config"]["scenarios"][0], "create");
+        assert_eq!(json["timing"]["elapsed_s"], 2.0);
+        assert_eq!(json["delayed_sends"], 4);
+        assert_eq!(json["dropped_sends"], 0);
+        assert_eq!(json["total"]["success"], 3);
+        assert_eq!(json["total"]["error"], 1);
+        assert_eq!(json["total"]["timeout"], 1);
+        assert_eq!(json["total"]["retry"], 1);
+        assert_eq!(json["total"]["success_rate"], 75.0);
+        assert_eq!(json["total"]["status_codes"]["201"], 2);
+        assert_eq!(json["scenarios"][0]["name"], "create");
+        assert_eq!(json["scenarios"][0]["rps"], 1.0);
+        assert_eq!(json["scenarios"][1]["status_codes"]["200"], 1);
+        let mean = json["scenarios"][0]["latency_ms"]["mean"].as_f64().unwrap();
+        assert!((mean - 2.0).abs() < 0.01);
+        assert_eq!(json["aborted"], serde_json::Value::Null);
+        assert_eq!(
+            json["scenarios"][1]["failures"][0]["reason"],
+            "Expected status code: 200, got: 500"
+        );
+        assert_eq!(json["total"]["failures"][0]["count"], 1);
+        let max = json["scenarios"][0]["response_time_ms"]["max"]
+            .as_f64()
+            .unwrap();
+        assert!((max - 9.0).abs() < 0.01);
+    }
+
+    #[test]
+    fn test_report_csv() {
+        let report = Report::new(&config(), &aggregated_report(), Local::now());
+        let csv = report.to_csv();
+        let (summary, lines): (Vec<&str>, Vec<&str>) =
+            csv.lines().partition(|line| line.starts_with("# "));
+
+        assert!(summary.contains(&"# base_url,http://localhost:8080/"));
+        assert!(summary.contains(&"# mode,rate"));
+        assert!(summary.contains(&"# batch_size,5"));
+        assert!(summary.contains(&"# scenarios,create"));
+        assert!(summary.contains(&"# delayed_sends,4"));
+        assert!(summary.contains(&"# elapsed_s,2.000"));
+        assert!(summary.iter().any(|line| line.starts_with("# started_at,")));
+
+        assert_eq!(lines.len(), 4);
+        assert!(lines[0].starts_with("scenario,success,error,timeout,reset,"));
+        assert!(lines[0].ends_with(",corrected_p999_ms,corrected_max_ms,status_codes,failures"));
+        assert!(lines[1].starts_with("create,2,0,0,0,0,0,1,100.00,1.000,"));
+        assert!(lines[1].ends_with(",9.007,201:2,"));
+        assert!(lines[2].starts_with("\"update, v2\",1,1,1,0,0,0,0,50.00,"));
+        assert!(lines[2].ends_with(",200:1,\"Expected status code: 200, got: 500 (1)\""));
+        assert!(lines[3].starts_with("total,3,1,1,0,0,0,1,75.00,1.500,"));
+        assert!(lines[3].contains(",200:1 201:2,"));
+    }
+}
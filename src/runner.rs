@@ -1,29 +1,60 @@
+use crate::arrival::Arrivals;
+use crate::budget::ErrorBudget;
+use crate::capacity::CapacitySearch;
+use crate::capacity::StepResult;
 use crate::config;
+use crate::config::AssertFailure;
+use crate::config::AutoThrottleConfig;
+use crate::config::Backpressure;
+use crate::config::ConcurrencyConfig;
+use crate::config::PingConfig;
+use crate::config::ReconnectConfig;
 use crate::config::RunnerConfig;
-use crate::http_api::{send_request, HttpRequest, HttpResponse};
+use crate::connection::ConnectionPool;
+use crate::connection::Connector;
+use crate::flow::Flow;
+use crate::flow::FlowMix;
+use crate::flow::NextStep;
+use crate::flow::MAX_GOTO_JUMPS;
+use crate::http_api::{send_request, ErrorKind, HttpError, HttpRequest, HttpResponse};
+use crate::interval::IntervalSampler;
+use crate::load_profile::LoadProfile;
+use crate::load_profile::LoadStep;
 use crate::scenario::Global;
-use crate::scenario::Scenario;
 use crate::script::ScriptContext;
+use crate::stats;
 use crate::stats::ApiStats;
-use bytes::Bytes;
-use h2::client;
-use h2::client::SendRequest;
+use crate::stats::LatencySummary;
+use crate::stats::ScenarioStats;
+use hdrhistogram::Histogram;
 use std::cell::RefCell;
 use std::error::Error;
 use std::sync::Arc;
 use std::sync::RwLock;
 use std::time::Instant;
-use tokio::net::TcpStream;
 use tokio::sync::mpsc::channel;
 use tokio::sync::mpsc::{Receiver, Sender};
+use tokio::sync::Semaphore;
+use tokio::task::JoinHandle;
 use tokio::time;
 use tokio::time::Duration;
 
 pub struct Runner {
-    param: RunParameter,
-    target_address: String,
-    first_scenario: Scenario,
-    subsequent_scenarios: Vec<Scenario>,
+    load_profile: LoadProfile,
+    auto_throttle: Option<AutoThrottleConfig>,
+    concurrency: Option<ConcurrencyConfig>,
+    batch_size: Option<u32>,
+    arrivals: Option<Arrivals>,
+    connector: Arc<Connector>,
+    connections: u16,
+    max_in_flight: Option<u32>,
+    backpressure: Backpressure,
+    on_assert_failure: AssertFailure,
+    reconnect: ReconnectConfig,
+    ping: Option<PingConfig>,
+    flows: Vec<Flow>,
+    flow_mix: FlowMix,
+    scenario_names: Vec<String>,
 }
 
 impl Runner {
@@ -34,68 +65,488 @@ impl Runner {
             config::BatchSize::Fixed(size) => Some(size),
         };
 
-        // duration
-        if config.duration.as_secs() <= 0 {
-            return Err("Duration must be at least 1s".into());
+        // load profile, a single stage at target_rps when not set
+        let load_profile = match &config.load_profile {
+            Some(stages) => LoadProfile::new(stages)?,
+            None => {
+                if config.duration.as_secs() == 0 {
+                    return Err("Duration must be at least 1s".into());
+                }
+                LoadProfile::constant(config.target_rps, config.duration)
+            }
+        };
+
+        // capacity search replaces the load profile
+        if let Some(auto_throttle) = &config.auto_throttle {
+            if config.load_profile.is_some() {
+                return Err("Either load_profile or auto_throttle can be set, not both".into());
+            }
+            CapacitySearch::new(auto_throttle)?;
         }
-        let duration_s = config.duration.as_secs() as u32;
 
-        // target address
-        let url = config.base_url.clone();
-        let address = url
-            .strip_prefix("http://")
-            .or_else(|| url.strip_prefix("https://"))
-            .unwrap_or(&url);
-        let address = address.trim_end_matches('/');
+        // closed loop with a fixed number of virtual users for `duration`
+        let concurrency = match config.mode {
+            config::RunMode::Rate => None,
+            config::RunMode::Concurrency => {
+                let concurrency = config
+                    .concurrency
+                    .clone()
+                    .ok_or("mode concurrency requires a concurrency section")?;
+                if concurrency.users == 0 {
+                    return Err("concurrency users must be at least 1".into());
+                }
+                if config.load_profile.is_some()
+                    || config.auto_throttle.is_some()
+                    || config.arrival.is_some()
+                {
+                    return Err(
+                        "load_profile, auto_throttle and arrival are not supported in mode concurrency"
+                            .into(),
+                    );
+                }
+                // A dropped chain would be started again right away by its user
+                if config.max_in_flight.is_some() && config.backpressure == Backpressure::Drop {
+                    return Err("backpressure drop is not supported in mode concurrency".into());
+                }
+                Some(concurrency)
+            }
+        };
+
+        // randomized arrivals replace the batches
+        let arrivals = match &config.arrival {
+            Some(arrival) => {
+                let arrivals = Arrivals::new(arrival)?;
+                arrivals.is_randomized().then_some(arrivals)
+            }
+            None => None,
+        };
 
-        // scenarios
-        let first_scenario_config = config.scenarios.get(0).ok_or("No scenario defined")?;
+        if config.max_in_flight == Some(0) {
+            return Err("max_in_flight must be at least 1".into());
+        }
 
-        let mut subsequent_scenarios_config = vec![];
-        for scenario_config in config.scenarios.iter().skip(1) {
-            subsequent_scenarios_config.push(scenario_config);
+        if let Some(ping) = &config.ping {
+            if ping.interval.is_zero() || ping.timeout.is_zero() {
+                return Err("ping interval and timeout must be greater than 0".into());
+            }
         }
-        let mut subsequent_scenarios = vec![];
-        for scenario_config in subsequent_scenarios_config.iter() {
-            subsequent_scenarios.push(Scenario::new(scenario_config, &config.base_url));
+
+        if config.reconnect.max_attempts == 0 {
+            return Err("reconnect max_attempts must be at least 1".into());
         }
 
-        let scenario_count = subsequent_scenarios_config.len() + 1;
+        // target connection
+        let connector = Connector::new(&config.base_url, config.tls.as_ref(), &config.http2)?;
+
+        // flows of scenarios, a plain scenario list is a single flow
+        if config.flows.is_some() && !config.scenarios.is_empty() {
+            return Err("Either scenarios or flows can be set, not both".into());
+        }
+        if config.flows.is_none() && config.scenarios.is_empty() {
+            return Err("No scenario defined".into());
+        }
+        let flow_configs = config.flows();
+        let flows = Flow::new_all(&flow_configs, &config.base_url)?;
+        let scenario_names = config.scenario_names();
 
         Ok(Runner {
-            param: RunParameter::new(config.target_rps, duration_s, batch_size, scenario_count),
-            target_address: address.into(),
-            first_scenario: Scenario::new(first_scenario_config, &config.base_url),
-            subsequent_scenarios,
+            load_profile,
+            auto_throttle: config.auto_throttle,
+            concurrency,
+            batch_size,
+            arrivals,
+            connector: Arc::new(connector),
+            connections: config.connections_per_runner,
+            max_in_flight: config.max_in_flight,
+            backpressure: config.backpressure,
+            on_assert_failure: config.assert.on_failure,
+            reconnect: config.reconnect,
+            ping: config.ping,
+            flows,
+            flow_mix: FlowMix::new(&flow_configs),
+            scenario_names,
         })
     }
 
-    pub async fn run(&mut self, global: Global) -> Result<RunReport, Box<dyn Error>> {
+    pub async fn run(
+        &mut self,
+        global: Global,
+        api_stats: Arc<ApiStats>,
+        sampler: Option<IntervalSampler>,
+        budget: Arc<ErrorBudget>,
+    ) -> Result<RunReport, Box<dyn Error>> {
         let global = Arc::new(RwLock::new(global));
 
-        let tcp = TcpStream::connect(&self.target_address).await?;
-        let (client, h2) = client::handshake(tcp).await?;
+        let scenario_names: Vec<&str> = self.scenario_names.iter().map(|s| s.as_str()).collect();
+        api_stats.set_scenarios(&scenario_names);
 
-        tokio::task::spawn(async move {
-            if let Err(e) = h2.await {
-                log::error!("Unexpected error: {:?}", e);
-            }
+        let pool = ConnectionPool::connect(
+            Arc::clone(&self.connector),
+            self.connections,
+            self.reconnect.clone(),
+            self.ping.clone(),
+            Arc::clone(&api_stats),
+        )
+        .await?;
+
+        let limit = self.max_in_flight.map(|max_in_flight| InFlightLimit {
+            permits: Arc::new(Semaphore::new(max_in_flight as usize)),
+            backpressure: self.backpressure,
         });
 
         let (eventloop_tx, eventloop_rx) = channel(32);
-        tokio::spawn(async move {
-            // TODO remove unwrap
-            Self::event_loop(client, eventloop_rx).await.unwrap();
+        let loop_stats = Arc::clone(&api_stats);
+        let event_loop = tokio::spawn(async move {
+            Self::event_loop(pool, limit, loop_stats, eventloop_rx)
+                .await
+                .map_err(|e| e.to_string())
         });
+        let mut run_ctx = RunContext {
+            global,
+            api_stats: Arc::clone(&api_stats),
+            budget,
+            eventloop_tx,
+            event_loop,
+        };
 
-        let param = &self.param;
-        let total_iterations = param.total_requests as f64 / param.batch_size as f64;
-        let total_iterations = total_iterations.ceil() as u32;
-        let scenario_count = self.param.scenario_count as u32;
-        let total_requests = total_iterations * param.batch_size * scenario_count;
+        let start = Instant::now();
+        let sampler = sampler.map(|sampler| sampler.start(Arc::clone(&api_stats)));
+
+        let mut capacity_rps = None;
+        let mut virtual_users = 0;
+        if let Some(config) = self.concurrency.clone() {
+            self.run_concurrency(&mut run_ctx, &config).await?;
+            virtual_users = config.users;
+        } else if let Some(config) = self.auto_throttle.clone() {
+            capacity_rps = self.search_capacity(&mut run_ctx, &config).await?;
+        } else {
+            self.run_load_profile(&mut run_ctx).await?;
+        }
+
+        // while api_stats.get_success() + api_stats.get_error() < total_requests {
+        //     tokio::time::sleep(tokio::time::Duration::from_millis(10)).await;
+        // }
+
+        // Terminate the event loop
+        run_ctx.eventloop_tx.send(Event::Terminate).await.unwrap();
+        if let Some(sampler) = sampler {
+            sampler.abort();
+        }
+
+        let success_count = api_stats.get_success();
+        let error_count = api_stats.get_error();
+        let total_count = success_count + error_count;
+        let rtt_histogram = api_stats.get_rtt();
+        let rtt = LatencySummary::new(&rtt_histogram);
+        let response_time_histogram = api_stats.get_response_time();
+        let total_retry = api_stats.get_retry();
+        let reconnect_count = api_stats.get_reconnect();
+        let ping_count = api_stats.get_ping();
+        let ping_timeout_count = api_stats.get_ping_timeout();
+        let total_ping_rtt = Duration::from_micros(api_stats.get_ping_rtt());
+        let delayed_count = api_stats.get_delayed();
+        let dropped_count = api_stats.get_dropped();
+
+        let elapsed = start.elapsed();
+        let elapsed_s = elapsed.as_secs() as f64 + elapsed.subsec_millis() as f64 / 1000.0;
+        let rps = success_count as f64 / (elapsed.as_micros() as f64 / 1_000_000.0);
+
+        log::info!(
+            "Elapsed: {:.3}s, RPS: {:.3}, RTT: {:.3}ms, p99: {:.3}ms, Error: ({}/{}), Retry: {}, Reconnect: {}",
+            elapsed_s,
+            rps,
+            rtt.mean,
+            rtt.p99,
+            error_count,
+            total_count,
+            total_retry,
+            reconnect_count
+        );
+
+        let report = RunReport {
+            rps,
+            elapsed,
+            success_count,
+            error_count,
+            rtt_histogram,
+            response_time_histogram,
+            reconnect_count,
+            ping_count,
+            ping_timeout_count,
+            total_ping_rtt,
+            delayed_count,
+            dropped_count,
+            scenario_stats: api_stats.get_scenarios(),
+            capacity_rps,
+            virtual_users,
+        };
+        Ok(report)
+    }
+
+    async fn run_load_profile(&mut self, run_ctx: &mut RunContext) -> Result<(), Box<dyn Error>> {
+        let steps = self.load_profile.steps().to_vec();
+        self.run_steps(run_ctx, &steps, true).await
+    }
+
+    // Closed loop, every virtual user starts the chain again as soon as its
+    // previous one ended, after `think_time`
+    async fn run_concurrency(
+        &mut self,
+        run_ctx: &mut RunContext,
+        config: &ConcurrencyConfig,
+    ) -> Result<(), Box<dyn Error>> {
+        let duration = self.load_profile.duration();
+        let start = Instant::now();
+        let deadline = start + duration;
+        log::info!(
+            "Running {} virtual user(s) for {:?}, Think Time: {:?}",
+            config.users,
+            duration,
+            config.think_time
+        );
+
+        let (resp_tx, mut resp_rx) = channel(32);
+        let (think_tx, mut think_rx) = channel(config.users as usize);
+
+        for _ in 0..config.users {
+            self.start_chain(run_ctx, &resp_tx, Instant::now()).await?;
+        }
+
+        // Users running a chain or thinking, they stop once the run is over
+        let mut active_users = config.users;
+        let mut completed_chains = 0;
+        while active_users > 0 {
+            tokio::select! {
+                message = Self::recv_response(run_ctx, &mut resp_rx) => {
+                    let Some(message) = message? else {
+                        break;
+                    };
+                    if self.handle_message(run_ctx, message, &resp_tx).await? {
+                        continue;
+                    }
+                    completed_chains += 1;
+
+                    if Instant::now() >= deadline || run_ctx.budget.is_exceeded() {
+                        active_users -= 1;
+                    } else if config.think_time.is_zero() {
+                        self.start_chain(run_ctx, &resp_tx, Instant::now()).await?;
+                    } else {
+                        let think_tx = think_tx.clone();
+                        let think_time = config.think_time;
+                        tokio::spawn(async move {
+                            time::sleep(think_time).await;
+                            let _ = think_tx.send(()).await;
+                        });
+                    }
+                }
+                Some(_) = think_rx.recv() => {
+                    if Instant::now() >= deadline || run_ctx.budget.is_exceeded() {
+                        active_users -= 1;
+                    } else {
+                        self.start_chain(run_ctx, &resp_tx, Instant::now()).await?;
+                    }
+                }
+            }
+        }
 
+        // Users finish their last chain after the deadline
         log::info!(
-            "Sending Total Req: {}, Iteration: {}, Target RPS: {} TPS: {}, Batch Size: {}, Interval: {}",
+            "{} virtual user(s) completed {} scenario chain(s), {:.3} chains/s",
+            config.users,
+            completed_chains,
+            completed_chains as f64 / start.elapsed().as_secs_f64()
+        );
+        Ok(())
+    }
+
+    // Runs steps at increasing rate until the server no longer keeps up,
+    // returns the highest rate that was stable
+    async fn search_capacity(
+        &mut self,
+        run_ctx: &mut RunContext,
+        config: &AutoThrottleConfig,
+    ) -> Result<Option<u32>, Box<dyn Error>> {
+        let requests_per_chain = self.flow_mix.requests_per_chain();
+        let mut search = CapacitySearch::new(config)?;
+
+        loop {
+            let rps = search.current_rps();
+            let param = RunParameter::new(
+                rps,
+                search.step_duration(),
+                self.batch_size,
+                requests_per_chain,
+            );
+            log::info!(
+                "Capacity search: Sending {} RPS for {:?}, Batch Size: {}, Interval: {}",
+                rps,
+                search.step_duration(),
+                param.batch_size,
+                param.interval.as_secs_f64()
+            );
+
+            let api_stats = Arc::clone(&run_ctx.api_stats);
+            let success_before = api_stats.get_success();
+            let error_before = api_stats.get_error();
+            // Corrected for coordinated omission, a server that falls behind
+            // delays the sends and would otherwise look stable
+            let response_time_before = api_stats.get_response_time();
+
+            let step = LoadStep {
+                stage: 0,
+                target_rps: rps,
+                duration: search.step_duration(),
+            };
+            self.run_steps(run_ctx, &[step], false).await?;
+
+            // Only what happened during this step
+            let success = api_stats.get_success() - success_before;
+            let error = api_stats.get_error() - error_before;
+            let mut response_time = api_stats.get_response_time();
+            response_time.subtract(&response_time_before)?;
+
+            let total = success + error;
+            let error_rate = if total == 0 {
+                0.0
+            } else {
+                error as f64 / total as f64 * 100.0
+            };
+            let result = StepResult {
+                p99: Duration::from_micros(response_time.value_at_quantile(0.99)),
+                error_rate,
+            };
+            log::info!(
+                "Capacity search: {} RPS is {}, p99: {:.3}ms, Error: {:.2}% ({}/{})",
+                rps,
+                if search.is_stable(&result) {
+                    "stable"
+                } else {
+                    "unstable"
+                },
+                result.p99.as_secs_f64() * 1000.0,
+                error_rate,
+                error,
+                total
+            );
+
+            if run_ctx.budget.is_exceeded() || search.next(&result).is_none() {
+                break;
+            }
+        }
+
+        match search.highest_stable() {
+            Some(rps) => log::info!("Capacity search: Highest stable RPS: {}", rps),
+            None => log::warn!("Capacity search: No stable rate found"),
+        }
+        Ok(search.highest_stable())
+    }
+
+    // Sends the steps one after the other from a single loop, the rate
+    // changes at the step boundaries while earlier chains go on. Chains are
+    // only waited for once the last step is over.
+    async fn run_steps(
+        &mut self,
+        run_ctx: &mut RunContext,
+        steps: &[LoadStep],
+        log_stages: bool,
+    ) -> Result<(), Box<dyn Error>> {
+        let requests_per_chain = self.flow_mix.requests_per_chain();
+        let (resp_tx, mut resp_rx) = channel(32);
+
+        let mut steps = steps.iter().peekable();
+        let mut step: Option<(&LoadStep, RunParameter)> = None;
+        let mut step_end = time::Instant::now();
+        // Batches, or single chains with randomized arrivals, are started at
+        // `next_start`, one gap of the current rate after the previous one
+        let mut last_start: Option<time::Instant> = None;
+        let mut next_start = step_end;
+        let mut active_chains = 0;
+        let mut previous_stage = None;
+        loop {
+            // No new chain once the error budget is exceeded
+            let sending = steps.peek().is_some() || time::Instant::now() < step_end;
+            let sending = sending && !run_ctx.budget.is_exceeded();
+            if !sending && active_chains == 0 {
+                break;
+            }
+
+            if sending && time::Instant::now() >= step_end {
+                let next = steps.next().expect("Steps are left");
+                let param = RunParameter::new(
+                    next.target_rps,
+                    next.duration,
+                    self.batch_size,
+                    requests_per_chain,
+                );
+                if log_stages {
+                    let stage_start = previous_stage != Some(next.stage);
+                    Self::log_step(next, &param, requests_per_chain, stage_start);
+                }
+                previous_stage = Some(next.stage);
+
+                let step_start = step_end;
+                step_end += next.duration;
+                next_start = match last_start {
+                    Some(last_start) => (last_start + self.start_gap(&param)).max(step_start),
+                    None => step_start,
+                };
+                step = Some((next, param));
+                continue;
+            }
+
+            let (current, param) = step.as_ref().expect("Step is set");
+            let starting = sending && current.target_rps > 0 && next_start < step_end;
+            tokio::select! {
+                _ = time::sleep_until(next_start), if starting => {
+                    // Time the chains were due, behind it when the runner was busy
+                    let scheduled_at = next_start.into_std();
+                    let chains = if self.arrivals.is_some() { 1 } else { param.batch_size };
+                    for _ in 0..chains {
+                        self.start_chain(run_ctx, &resp_tx, scheduled_at).await?;
+                    }
+                    active_chains += chains;
+                    last_start = Some(next_start);
+                    next_start += self.start_gap(param);
+                }
+                // Nothing more to start in this step
+                _ = time::sleep_until(step_end), if sending && !starting => {}
+                message = Self::recv_response(run_ctx, &mut resp_rx), if active_chains > 0 => {
+                    let Some(message) = message? else {
+                        break;
+                    };
+                    if !self.handle_message(run_ctx, message, &resp_tx).await? {
+                        active_chains -= 1;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // Time between two starts at the rate of `param`, random around the
+    // chain rate with randomized arrivals
+    fn start_gap(&mut self, param: &RunParameter) -> Duration {
+        match self.arrivals.as_mut() {
+            Some(arrivals) => arrivals.next_gap(param.target_tps as f64),
+            None => param.interval,
+        }
+    }
+
+    // Ramps change rate every step, only the start of a stage is worth an info line
+    fn log_step(step: &LoadStep, param: &RunParameter, requests_per_chain: f64, stage_start: bool) {
+        let total_iterations = param.total_requests as f64 / param.batch_size as f64;
+        let total_iterations = total_iterations.ceil() as u32;
+        let total_requests =
+            (total_iterations as f64 * param.batch_size as f64 * requests_per_chain).round();
+        let level = if stage_start {
+            log::Level::Info
+        } else {
+            log::Level::Debug
+        };
+        log::log!(
+            level,
+            "Stage #{}: Sending Total Req: {}, Iteration: {}, Target RPS: {} TPS: {}, Batch Size: {}, Interval: {}",
+            step.stage,
             total_requests,
             total_iterations,
             param.target_rps,
@@ -103,167 +554,441 @@ impl Runner {
             param.batch_size,
             param.interval.as_secs_f64()
         );
+    }
 
-        let start = Instant::now();
-        let api_stats = Arc::new(ApiStats::new());
+    // Sends the first request of a new scenario chain, due at `scheduled_at`
+    async fn start_chain(
+        &mut self,
+        run_ctx: &RunContext,
+        resp_tx: &Sender<ChainMessage>,
+        scheduled_at: Instant,
+    ) -> Result<(), Box<dyn Error>> {
+        let flow_id = self.flow_mix.next();
+        let flow = &mut self.flows[flow_id];
+        let scenario = &mut flow.scenarios[0];
+        log::debug!(
+            "Running flow '{}' scenario #0: {}",
+            flow.name,
+            scenario.name
+        );
 
-        let mut interval = time::interval(param.interval);
-        for _ in 0..total_iterations {
-            interval.tick().await;
+        let ctx = EventContext {
+            flow_id,
+            scenario_id: 0,
+            iteration: 1,
+            iterations: scenario.repeat_iterations(),
+            jumps: 0,
+            scheduled_at,
+            script_ctx: RefCell::new(ScriptContext::new(Arc::clone(&run_ctx.global))),
+        };
+        self.schedule_scenario(run_ctx, ctx, resp_tx).await
+    }
 
-            let (resp_tx, mut resp_rx) = channel(32);
+    async fn recv_response(
+        run_ctx: &mut RunContext,
+        resp_rx: &mut Receiver<ChainMessage>,
+    ) -> Result<Option<ChainMessage>, Box<dyn Error>> {
+        tokio::select! {
+            message = resp_rx.recv() => Ok(message),
+            result = &mut run_ctx.event_loop => {
+                let reason = match result {
+                    Ok(Ok(_)) => "terminated".to_string(),
+                    Ok(Err(e)) => e,
+                    Err(e) => e.to_string(),
+                };
+                Err(format!("Event loop stopped: {}", reason).into())
+            }
+        }
+    }
 
-            for _ in 0..param.batch_size {
-                let scenario = &mut self.first_scenario;
-                log::debug!("Running scenario #0: {}", scenario.name);
+    // Returns false once the chain has ended
+    async fn handle_message(
+        &mut self,
+        run_ctx: &RunContext,
+        message: ChainMessage,
+        resp_tx: &Sender<ChainMessage>,
+    ) -> Result<bool, Box<dyn Error>> {
+        match message {
+            ChainMessage::Response(ctx, response) => {
+                self.handle_response(run_ctx, ctx, response, resp_tx).await
+            }
+            ChainMessage::Resume(_) if run_ctx.budget.is_exceeded() => Ok(false),
+            ChainMessage::Resume(ctx) => {
+                self.send_scenario(run_ctx, ctx, resp_tx).await?;
+                Ok(true)
+            }
+        }
+    }
 
-                // First Pre Script
-                let mut script_ctx = ScriptContext::new(Arc::clone(&global));
-                scenario.run_pre_script(&mut script_ctx);
+    // Records the response and sends the request of the next scenario in the
+    // chain, returns false once the chain has ended
+    async fn handle_response(
+        &mut self,
+        run_ctx: &RunContext,
+        ctx: EventContext,
+        response: Result<HttpResponse, HttpError>,
+        resp_tx: &Sender<ChainMessage>,
+    ) -> Result<bool, Box<dyn Error>> {
+        run_ctx.api_stats.dec_in_flight();
 
-                // First HTTP request
-                let http_request = scenario.new_request(&script_ctx).unwrap();
+        // Get Scenario
+        let flow_id = ctx.flow_id;
+        let scenario_id = ctx.scenario_id;
+        let flow = &self.flows[flow_id];
+        let cur_scenario = &flow.scenarios[scenario_id];
+        let stat_id = flow.first_stat + scenario_id;
 
-                let ctx = EventContext {
-                    scenario_id: 0,
-                    script_ctx: RefCell::new(script_ctx),
-                };
-                eventloop_tx
-                    .send(Event::SendMessage(ctx, http_request, resp_tx.clone()))
-                    .await?;
+        // A matching `next` rule decides, otherwise repeat the scenario or
+        // move on to the next one in the flow
+        let next_step = match response {
+            Ok(response) => self.record_response(run_ctx, &ctx, stat_id, &response),
+            Err(HttpError::Dropped) => {
+                // Generator side, not a failure of the server
+                log::debug!("Scenario '{}' dropped", cur_scenario.name);
+                return Ok(false);
             }
+            Err(e) => {
+                log::error!("Scenario '{}' failed: {}", cur_scenario.name, e);
+                run_ctx.api_stats.inc_error();
+                run_ctx.api_stats.update_scenario(stat_id, |stats| {
+                    stats.error += 1;
+                    match e.kind() {
+                        ErrorKind::Timeout => stats.timeout += 1,
+                        ErrorKind::Reset => stats.reset += 1,
+                        ErrorKind::Closed => stats.connection_closed += 1,
+                        ErrorKind::Protocol => stats.protocol_error += 1,
+                        ErrorKind::Other => {}
+                    }
+                    stats.inc_failure(&e.to_string());
+                });
+                Self::record_budget(run_ctx, true);
 
-            let total_response = param.batch_size * scenario_count;
-
-            for _ in 0..total_response {
-                if let Some((ctx, response)) = resp_rx.recv().await {
-                    log::debug!("Response Status: {:?}", response.status);
-                    log::debug!("Response Body: {:?}", response.body);
-                    api_stats.inc_retry(response.retry_count.into());
+                // Request never got a response, the rest of the chain is
+                // aborted unless a rule for the error says otherwise
+                let next_step =
+                    flow.next_error_step(scenario_id, e.kind(), &ctx.script_ctx.borrow());
+                Some(next_step.unwrap_or(NextStep::End))
+            }
+        };
+        if run_ctx.budget.is_exceeded() {
+            return Ok(false);
+        }
+        let repeat = match next_step {
+            Some(NextStep::End) => {
+                log::debug!("Chain ended after scenario '{}'", cur_scenario.name);
+                return Ok(false);
+            }
+            // A rule going back in the flow may loop as long as the server
+            // gives the same answer
+            Some(NextStep::Goto(_)) if ctx.jumps >= MAX_GOTO_JUMPS => {
+                let reason = format!("Chain ended after {} goto jumps", ctx.jumps);
+                log::error!("Scenario '{}': {}", cur_scenario.name, reason);
+                Self::record_failure(run_ctx, stat_id, &reason);
+                return Ok(false);
+            }
+            Some(NextStep::Goto(_)) => false,
+            Some(NextStep::Continue) | None => {
+                ctx.iteration < ctx.iterations
+                    && !cur_scenario.repeat_done(&ctx.script_ctx.borrow())
+            }
+        };
+        let jumps = match next_step {
+            Some(NextStep::Goto(_)) => ctx.jumps + 1,
+            _ => ctx.jumps,
+        };
+        let (next_id, iteration) = match next_step {
+            Some(NextStep::Goto(id)) => (id, 1),
+            _ if repeat => (scenario_id, ctx.iteration + 1),
+            _ => (scenario_id + 1, 1),
+        };
 
-                    // Get Scenario
-                    let scenario_id = ctx.scenario_id;
-                    let cur_scenario = if scenario_id == 0 {
-                        &self.first_scenario
-                    } else {
-                        &self.subsequent_scenarios[scenario_id - 1]
-                    };
+        let Some(scenario) = self.flows[flow_id].scenarios.get_mut(next_id) else {
+            log::debug!("All scenarios completed");
+            return Ok(false);
+        };
+        let iterations = if repeat {
+            ctx.iterations
+        } else {
+            scenario.repeat_iterations()
+        };
+        log::debug!(
+            "Running scenario #{}: {} ({}/{})",
+            next_id,
+            scenario.name,
+            iteration,
+            iterations
+        );
 
-                    if !cur_scenario.assert_response(&response) {
-                        // Error Stats
-                        api_stats.inc_error();
-                    } else {
-                        // Success Stats
-                        let round_trip_time = response.request_start.elapsed().as_micros() as u64;
-                        api_stats.inc_rtt(round_trip_time);
-                        api_stats.inc_success();
+        let ctx = EventContext {
+            flow_id,
+            scenario_id: next_id,
+            iteration,
+            iterations,
+            jumps,
+            // Due as soon as the previous response is in
+            scheduled_at: Instant::now(),
+            script_ctx: ctx.script_ctx,
+        };
+        self.schedule_scenario(run_ctx, ctx, resp_tx).await?;
+        Ok(true)
+    }
 
-                        {
-                            let mut script_ctx = ctx.script_ctx.borrow_mut();
+    // Records the stats of a response and runs its post-script, returns the
+    // step of a matching `next` rule
+    fn record_response(
+        &self,
+        run_ctx: &RunContext,
+        ctx: &EventContext,
+        stat_id: usize,
+        response: &HttpResponse,
+    ) -> Option<NextStep> {
+        let flow = &self.flows[ctx.flow_id];
+        let cur_scenario = &flow.scenarios[ctx.scenario_id];
 
-                            // Get new variables from response to pass to next scenario
-                            cur_scenario
-                                .from_response(&mut script_ctx, &response)
-                                .unwrap();
+        log::debug!("Response Status: {:?}", response.status);
+        log::debug!("Response Body: {:?}", response.body);
+        run_ctx.api_stats.inc_retry(response.retry_count.into());
+        run_ctx.api_stats.update_scenario(stat_id, |stats| {
+            stats.inc_status(response.status.as_u16());
+            stats.retry += response.retry_count as u32;
+        });
 
-                            // Post scenario
-                            cur_scenario.run_post_script(&mut script_ctx);
-                        }
-                    }
+        let mut result = cur_scenario.assert_response(response);
+        if result.is_ok() {
+            let mut script_ctx = ctx.script_ctx.borrow_mut();
 
-                    // Check if there are subsequent scenarios
-                    if let Some(scenario) = self.subsequent_scenarios.get_mut(scenario_id) {
-                        log::debug!("Running scenario #{}: {}", scenario_id + 1, scenario.name);
+            // Get new variables from response to pass to next scenario, then
+            // post scenario. An error there fails the request like an assertion.
+            let defined = cur_scenario
+                .from_response(&mut script_ctx, response)
+                .and_then(|_| cur_scenario.run_post_script(&mut script_ctx));
+            if let Err(e) = defined {
+                result = Err(e.to_string());
+            }
+        }
 
-                        // Pre Script
-                        let http_request: HttpRequest;
-                        {
-                            let mut script_ctx = ctx.script_ctx.borrow_mut();
-                            scenario.run_pre_script(&mut script_ctx);
-                            http_request = scenario.new_request(&script_ctx).unwrap();
-                        }
+        let passed = result.is_ok();
+        if let Err(reason) = result {
+            // Error Stats
+            log::error!("Scenario '{}' failed: {}", cur_scenario.name, reason);
+            Self::record_failure(run_ctx, stat_id, &reason);
+        } else {
+            Self::record_budget(run_ctx, false);
+            // Success Stats, service time from the stream being opened and
+            // response time from when the request was due
+            let round_trip_time = response.request_start.elapsed().as_micros() as u64;
+            let response_time = ctx.scheduled_at.elapsed().as_micros() as u64;
+            run_ctx.api_stats.inc_rtt(round_trip_time);
+            run_ctx.api_stats.inc_response_time(response_time);
+            run_ctx.api_stats.inc_success();
+            run_ctx.api_stats.update_scenario(stat_id, |stats| {
+                stats.success += 1;
+                stats.rtt_histogram.saturating_record(round_trip_time);
+                stats
+                    .response_time_histogram
+                    .saturating_record(response_time);
+            });
+        }
 
-                        eventloop_tx
-                            .send(Event::SendMessage(
-                                EventContext {
-                                    scenario_id: scenario_id + 1,
-                                    script_ctx: ctx.script_ctx,
-                                },
-                                http_request,
-                                resp_tx.clone(),
-                            ))
-                            .await?;
-                    } else {
-                        log::debug!("All scenarios completed");
-                    }
-                }
+        let next_step = flow.next_step(
+            ctx.scenario_id,
+            response.status,
+            passed,
+            &ctx.script_ctx.borrow(),
+        );
+        match next_step {
+            None if !passed && self.on_assert_failure == AssertFailure::AbortChain => {
+                Some(NextStep::End)
             }
+            next_step => next_step,
         }
+    }
 
-        // while api_stats.get_success() + api_stats.get_error() < total_requests {
-        //     tokio::time::sleep(tokio::time::Duration::from_millis(10)).await;
-        // }
+    // Failure of the chain rather than of a request
+    fn record_failure(run_ctx: &RunContext, stat_id: usize, reason: &str) {
+        run_ctx.api_stats.inc_error();
+        run_ctx.api_stats.update_scenario(stat_id, |stats| {
+            stats.error += 1;
+            stats.inc_failure(reason);
+        });
+        Self::record_budget(run_ctx, true);
+    }
 
-        // Terminate the event loop
-        eventloop_tx.send(Event::Terminate).await.unwrap();
+    fn record_budget(run_ctx: &RunContext, failed: bool) {
+        if let Some(reason) = run_ctx.budget.record(failed) {
+            log::error!("Error budget exceeded, {}, aborting the run", reason);
+        }
+    }
 
-        let success_count = api_stats.get_success();
-        let error_count = api_stats.get_error();
-        let total_count = success_count + error_count;
-        let total_rtt = Duration::from_micros(api_stats.get_rtt());
-        let total_retry = api_stats.get_retry();
+    // Sends the scenario of `ctx` once its delay is over. The delay is waited
+    // off the loop, other chains' responses are handled meanwhile.
+    async fn schedule_scenario(
+        &mut self,
+        run_ctx: &RunContext,
+        mut ctx: EventContext,
+        resp_tx: &Sender<ChainMessage>,
+    ) -> Result<(), Box<dyn Error>> {
+        let scenario = &self.flows[ctx.flow_id].scenarios[ctx.scenario_id];
+        let delay = scenario.delay();
+        if delay.is_zero() {
+            return self.send_scenario(run_ctx, ctx, resp_tx).await;
+        }
 
-        let elapsed = start.elapsed();
-        let elapsed_s = elapsed.as_secs() as f64 + elapsed.subsec_millis() as f64 / 1000.0;
-        let rps = success_count as f64 / (elapsed.as_micros() as f64 / 1_000_000.0);
-        let avg_rtt = total_rtt.as_millis() as f64 / success_count as f64;
+        log::debug!("Delay scenario '{}' by {:?}", scenario.name, delay);
+        ctx.scheduled_at += delay;
+        let resp_tx = resp_tx.clone();
+        tokio::spawn(async move {
+            time::sleep(delay).await;
+            // Receiver is gone once the run is aborted
+            let _ = resp_tx.send(ChainMessage::Resume(ctx)).await;
+        });
+        Ok(())
+    }
 
-        log::info!(
-            "Elapsed: {:.3}s, RPS: {:.3}, RTT: {:.3}ms, Error: ({}/{}), Retry: {}",
-            elapsed_s,
-            rps,
-            avg_rtt,
-            error_count,
-            total_count,
-            total_retry
-        );
+    // Runs the pre-script of the scenario of `ctx` and sends its request
+    async fn send_scenario(
+        &mut self,
+        run_ctx: &RunContext,
+        ctx: EventContext,
+        resp_tx: &Sender<ChainMessage>,
+    ) -> Result<(), Box<dyn Error>> {
+        let scenario = &mut self.flows[ctx.flow_id].scenarios[ctx.scenario_id];
 
-        let report = RunReport {
-            rps,
-            elapsed,
-            success_count,
-            error_count,
-            total_rtt,
-        };
-        Ok(report)
+        // Pre Script
+        let http_request;
+        {
+            let mut script_ctx = ctx.script_ctx.borrow_mut();
+            http_request = scenario
+                .run_pre_script(&mut script_ctx)
+                .and_then(|_| scenario.new_request(&script_ctx));
+        }
+        Self::dispatch(run_ctx, ctx, http_request, resp_tx).await
+    }
+
+    // Queues the request to the event loop, one that could not be built fails
+    // like a request without response
+    async fn dispatch(
+        run_ctx: &RunContext,
+        ctx: EventContext,
+        http_request: Result<HttpRequest, Box<dyn Error>>,
+        resp_tx: &Sender<ChainMessage>,
+    ) -> Result<(), Box<dyn Error>> {
+        run_ctx.api_stats.inc_in_flight();
+        match http_request {
+            Ok(http_request) => {
+                run_ctx
+                    .eventloop_tx
+                    .send(Event::SendMessage(ctx, http_request, resp_tx.clone()))
+                    .await?;
+            }
+            Err(e) => {
+                let e = HttpError::Failed(format!("Invalid request: {}", e));
+                // The runner is the receiver, it cannot wait on a full channel
+                let resp_tx = resp_tx.clone();
+                tokio::spawn(async move {
+                    let _ = resp_tx.send(ChainMessage::Response(ctx, Err(e))).await;
+                });
+            }
+        }
+        Ok(())
     }
 
     async fn event_loop(
-        mut client: SendRequest<Bytes>,
+        mut pool: ConnectionPool,
+        limit: Option<InFlightLimit>,
+        api_stats: Arc<ApiStats>,
         mut rx: Receiver<Event>,
     ) -> Result<(), Box<dyn std::error::Error>> {
         while let Some(event) = rx.recv().await {
             match event {
                 Event::SendMessage(ctx, request, tx) => {
-                    let future = send_request(&mut client, request).await?;
-                    // TODO handle timeout?
-
                     let scenario_id = ctx.scenario_id;
+
+                    // Waited for a permit or a free stream, counted once
+                    let mut delayed = false;
+
+                    // Held until the response is received, waiting for one here
+                    // stalls the runner until a request in flight completes
+                    let permit = match &limit {
+                        Some(limit) => match Arc::clone(&limit.permits).try_acquire_owned() {
+                            Ok(permit) => Some(permit),
+                            Err(_) if limit.backpressure == Backpressure::Drop => {
+                                api_stats.inc_dropped();
+                                // Runner may be blocked sending to this loop
+                                tokio::spawn(async move {
+                                    let _ = tx
+                                        .send(ChainMessage::Response(ctx, Err(HttpError::Dropped)))
+                                        .await;
+                                });
+                                continue;
+                            }
+                            Err(_) => {
+                                delayed = true;
+                                Some(Arc::clone(&limit.permits).acquire_owned().await?)
+                            }
+                        },
+                        None => None,
+                    };
+
+                    // A connection that went away before the stream was opened is
+                    // reconnected in the background and the request re-queued once
+                    let mut requeued = false;
+                    let (future, stream_guard) = loop {
+                        let Some(connection) = pool.least_loaded()? else {
+                            let e = HttpError::ConnectionClosed("No open connection".into());
+                            break (Err(e), None);
+                        };
+                        let stream_guard = Some(connection.stream_guard());
+                        let result = match connection.ready().await {
+                            Ok(waited) => {
+                                delayed |= waited;
+                                send_request(connection.client(), request.clone()).await
+                            }
+                            Err(e) => Err(e.into()),
+                        };
+                        match result {
+                            Ok(future) => break (Ok(future), stream_guard),
+                            Err(e) if !requeued && is_connection_error(e.as_ref()) => {
+                                log::warn!("Connection lost, re-queue request: {}", e);
+                                connection.mark_closed();
+                                requeued = true;
+                            }
+                            Err(e) => {
+                                let e = HttpError::from_error(e.as_ref(), "Error sending request");
+                                break (Err(e), stream_guard);
+                            }
+                        }
+                    };
+                    if delayed {
+                        api_stats.inc_delayed();
+                    }
+
                     log::debug!("Request {} sent", scenario_id);
 
                     tokio::spawn(async move {
-                        let response = future.await.unwrap(); // handle error?
-                        let response = response.unwrap(); // handle error?
-
-                        tx.send((
-                            EventContext {
-                                scenario_id,
-                                script_ctx: ctx.script_ctx,
+                        let response = match future {
+                            Ok(future) => match future.await {
+                                Ok(response) => response,
+                                Err(e) => {
+                                    Err(HttpError::Failed(format!("Response task failed: {}", e)))
+                                }
                             },
-                            response,
-                        ))
-                        .await
-                        .unwrap(); // handle error?
+                            Err(e) => Err(e),
+                        };
+                        drop(stream_guard);
+                        drop(permit);
+
+                        let ctx = EventContext {
+                            flow_id: ctx.flow_id,
+                            scenario_id,
+                            iteration: ctx.iteration,
+                            iterations: ctx.iterations,
+                            jumps: ctx.jumps,
+                            scheduled_at: ctx.scheduled_at,
+                            script_ctx: ctx.script_ctx,
+                        };
+                        // Receiver is gone once the run is aborted
+                        let _ = tx.send(ChainMessage::Response(ctx, response)).await;
                     });
                 }
                 Event::Terminate => {
@@ -276,17 +1001,51 @@ impl Runner {
     }
 }
 
+fn is_connection_error(e: &(dyn Error + 'static)) -> bool {
+    match e.downcast_ref::<h2::Error>() {
+        Some(e) => e.is_go_away() || e.is_io(),
+        None => false,
+    }
+}
+
+struct InFlightLimit {
+    permits: Arc<Semaphore>,
+    backpressure: Backpressure,
+}
+
+// State shared by the steps of a run
+struct RunContext {
+    global: Arc<RwLock<Global>>,
+    api_stats: Arc<ApiStats>,
+    // Shared by all runners, no chain is started or continued once exceeded
+    budget: Arc<ErrorBudget>,
+    eventloop_tx: Sender<Event>,
+    event_loop: JoinHandle<Result<(), String>>,
+}
+
 struct EventContext {
+    flow_id: usize,
+    // Position in the flow
     scenario_id: usize,
+    // Iteration of a repeated scenario, out of `iterations`
+    iteration: u32,
+    iterations: u32,
+    // `goto` rules followed so far
+    jumps: u32,
+    scheduled_at: Instant,
     script_ctx: RefCell<ScriptContext>,
 }
 
+#[allow(clippy::large_enum_variant)]
+enum ChainMessage {
+    Response(EventContext, Result<HttpResponse, HttpError>),
+    // Delay before the next scenario of the chain is over
+    Resume(EventContext),
+}
+
+#[allow(clippy::large_enum_variant)]
 enum Event {
-    SendMessage(
-        EventContext,
-        HttpRequest,
-        Sender<(EventContext, HttpResponse)>,
-    ),
+    SendMessage(EventContext, HttpRequest, Sender<ChainMessage>),
     Terminate,
 }
 
@@ -297,29 +1056,32 @@ pub struct RunParameter {
     pub batch_size: u32,
     pub interval: Duration,
     pub total_requests: u32,
-    pub scenario_count: usize,
 }
 
 impl RunParameter {
     pub fn new(
         target_rps: u32,
-        duration_s: u32,
+        duration: Duration,
         batch_size: Option<u32>,
-        scenario_count: usize,
+        requests_per_chain: f64,
     ) -> RunParameter {
-        let target_tps = target_rps / scenario_count as u32;
+        // Chains per second
+        let target_tps = (target_rps as f64 / requests_per_chain) as u32;
         let target_tps = if target_tps == 0 { 1 } else { target_tps };
 
         let batch_size = if let Some(batch_size) = batch_size {
             batch_size
         } else {
-            let batch_size = (target_tps / 200) as u32;
-            let batch_size = if batch_size == 0 { 1 } else { batch_size };
-            batch_size
+            let batch_size = target_tps / 200;
+            if batch_size == 0 {
+                1
+            } else {
+                batch_size
+            }
         };
         let batches_per_second = target_tps as f64 / batch_size as f64;
         let interval = Duration::from_secs_f64(1.0 / batches_per_second);
-        let total_requests = target_tps * duration_s;
+        let total_requests = (target_tps as f64 * duration.as_secs_f64()).round() as u32;
 
         RunParameter {
             target_rps,
@@ -327,7 +1089,6 @@ impl RunParameter {
             batch_size,
             interval,
             total_requests,
-            scenario_count,
         }
     }
 }
@@ -337,7 +1098,17 @@ pub struct RunReport {
     pub elapsed: Duration,
     pub success_count: u32,
     pub error_count: u32,
-    pub total_rtt: Duration,
+    pub rtt_histogram: Histogram<u64>,
+    pub response_time_histogram: Histogram<u64>,
+    pub reconnect_count: u32,
+    pub ping_count: u32,
+    pub ping_timeout_count: u32,
+    pub total_ping_rtt: Duration,
+    pub delayed_count: u32,
+    pub dropped_count: u32,
+    pub scenario_stats: Vec<ScenarioStats>,
+    pub capacity_rps: Option<u32>,
+    pub virtual_users: u32,
 }
 
 pub struct AggregatedReport {
@@ -345,9 +1116,24 @@ pub struct AggregatedReport {
     pub elapsed: Duration,
     pub total_success: u32,
     pub total_error: u32,
-    pub total_rtt: Duration,
+    pub rtt_histogram: Histogram<u64>,
+    pub response_time_histogram: Histogram<u64>,
+    pub total_reconnect: u32,
+    pub total_ping: u32,
+    pub total_ping_timeout: u32,
+    pub total_ping_rtt: Duration,
+    pub total_delayed: u32,
+    pub total_dropped: u32,
+    pub scenario_stats: Vec<ScenarioStats>,
+    pub capacity_rps: Option<u32>,
+    pub virtual_users: u32,
+    // Reason the error budget was exceeded
+    pub aborted: Option<String>,
 }
 
+// Failure reasons listed per scenario
+pub const TOP_FAILURES: usize = 5;
+
 impl AggregatedReport {
     pub fn new() -> AggregatedReport {
         AggregatedReport {
@@ -355,7 +1141,18 @@ impl AggregatedReport {
             elapsed: Duration::from_secs(0),
             total_success: 0,
             total_error: 0,
-            total_rtt: Duration::from_secs(0),
+            rtt_histogram: stats::new_histogram(),
+            response_time_histogram: stats::new_histogram(),
+            total_reconnect: 0,
+            total_ping: 0,
+            total_ping_timeout: 0,
+            total_ping_rtt: Duration::from_secs(0),
+            total_delayed: 0,
+            total_dropped: 0,
+            scenario_stats: vec![],
+            capacity_rps: None,
+            virtual_users: 0,
+            aborted: None,
         }
     }
 
@@ -364,21 +1161,777 @@ impl AggregatedReport {
         self.elapsed = self.elapsed.max(report.elapsed);
         self.total_success += report.success_count;
         self.total_error += report.error_count;
-        self.total_rtt += report.total_rtt;
+        self.rtt_histogram.add(&report.rtt_histogram).unwrap();
+        self.response_time_histogram
+            .add(&report.response_time_histogram)
+            .unwrap();
+        self.total_reconnect += report.reconnect_count;
+        self.total_ping += report.ping_count;
+        self.total_ping_timeout += report.ping_timeout_count;
+        self.total_ping_rtt += report.total_ping_rtt;
+        self.total_delayed += report.delayed_count;
+        self.total_dropped += report.dropped_count;
+        stats::merge_scenario_stats(&mut self.scenario_stats, &report.scenario_stats);
+        // Runners search their share of the load, the capacity is their sum
+        self.virtual_users += report.virtual_users;
+        if let Some(rps) = report.capacity_rps {
+            self.capacity_rps = Some(self.capacity_rps.unwrap_or(0) + rps);
+        }
     }
 
     pub fn report(&self) {
         let elapsed_s =
             self.elapsed.as_secs() as f64 + self.elapsed.subsec_millis() as f64 / 1000.0;
 
-        let avg_rtt = self.total_rtt.as_millis() as f64 / self.total_success as f64;
+        let rtt = LatencySummary::new(&self.rtt_histogram);
 
+        if self.virtual_users > 0 {
+            log::info!("Virtual Users: {}", self.virtual_users);
+        }
         log::info!("Total RPS: {:.3}", self.total_rps);
-        log::info!("Average Round Trip: {:.4}ms", avg_rtt);
+        log::info!("Average Round Trip: {:.4}ms", rtt.mean);
+        log::info!(
+            "Round Trip Percentiles (service time): p50: {:.3}ms, p90: {:.3}ms, p95: {:.3}ms, p99: {:.3}ms, p99.9: {:.3}ms, max: {:.3}ms",
+            rtt.p50,
+            rtt.p90,
+            rtt.p95,
+            rtt.p99,
+            rtt.p999,
+            rtt.max
+        );
+        // Includes the time requests waited behind schedule, as wrk2 reports it
+        let response_time = LatencySummary::new(&self.response_time_histogram);
+        log::info!(
+            "Response Time Percentiles (corrected): p50: {:.3}ms, p90: {:.3}ms, p95: {:.3}ms, p99: {:.3}ms, p99.9: {:.3}ms, max: {:.3}ms",
+            response_time.p50,
+            response_time.p90,
+            response_time.p95,
+            response_time.p99,
+            response_time.p999,
+            response_time.max
+        );
         log::info!("Elapsed: {:.3}s", elapsed_s);
         log::info!(
             "Success Rate: {:.2}%",
             self.total_success as f64 / (self.total_success + self.total_error) as f64 * 100.0
         );
+        log::info!("Reconnects: {}", self.total_reconnect);
+        if let Some(rps) = self.capacity_rps {
+            log::info!("Highest Stable RPS: {}", rps);
+        }
+        // The generator could not send at the configured rate, the results
+        // understate what the server can do
+        if self.total_delayed > 0 || self.total_dropped > 0 {
+            log::warn!(
+                "Sends Delayed: {}, Dropped: {}, limited by max_in_flight or the server's MAX_CONCURRENT_STREAMS",
+                self.total_delayed,
+                self.total_dropped
+            );
+        }
+
+        // Network round trip, compare with the request round trip to tell
+        // latency from server processing time
+        if self.total_ping > 0 || self.total_ping_timeout > 0 {
+            // All PINGs may have timed out
+            let avg_ping_rtt = if self.total_ping > 0 {
+                self.total_ping_rtt.as_micros() as f64 / 1000.0 / self.total_ping as f64
+            } else {
+                0.0
+            };
+            log::info!(
+                "Average PING Round Trip: {:.4}ms, Ping: {}, Timeout: {}",
+                avg_ping_rtt,
+                self.total_ping,
+                self.total_ping_timeout
+            );
+        }
+
+        // Per scenario breakdown, latency in ms
+        let name_width = self
+            .scenario_stats
+            .iter()
+            .map(|s| s.name.len())
+            .max()
+            .unwrap_or(0)
+            .max("Scenario".len());
+        log::info!(
+            "{:<name_width$} {:>8} {:>8} {:>8} {:>8} {:>9} {:>9} {:>9} {:>9} {:>9}",
+            "Scenario",
+            "Success",
+            "Error",
+            "Timeout",
+            "Retry",
+            "Mean",
+            "p50",
+            "p90",
+            "p99",
+            "Max"
+        );
+        for scenario in &self.scenario_stats {
+            let rtt = LatencySummary::new(&scenario.rtt_histogram);
+            log::info!(
+                "{:<name_width$} {:>8} {:>8} {:>8} {:>8} {:>9.3} {:>9.3} {:>9.3} {:>9.3} {:>9.3}",
+                scenario.name,
+                scenario.success,
+                scenario.error,
+                scenario.timeout,
+                scenario.retry,
+                rtt.mean,
+                rtt.p50,
+                rtt.p90,
+                rtt.p99,
+                rtt.max
+            );
+        }
+
+        for scenario in &self.scenario_stats {
+            let failures = scenario.top_failures(TOP_FAILURES);
+            if failures.is_empty() {
+                continue;
+            }
+            log::warn!("Top failures of '{}':", scenario.name);
+            for (reason, count) in failures {
+                log::warn!("{:>8} {}", count, reason);
+            }
+        }
+        if let Some(reason) = &self.aborted {
+            log::error!("Run aborted, error budget exceeded: {}", reason);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bytes::Bytes;
+    use http::Response;
+    use std::sync::atomic::AtomicBool;
+    use std::sync::atomic::Ordering;
+    use std::sync::Mutex;
+    use tokio::net::TcpListener;
+
+    // Arrival time and path of the requests the test server got
+    type Received = Arc<Mutex<Vec<(Instant, String)>>>;
+
+    // Plain h2 server answering `/<status>/<delay in ms>` with that status
+    // after the delay. `/close` closes the connection and the server, any
+    // other path is never answered.
+    async fn start_server(max_concurrent_streams: Option<u32>) -> (u16, Received) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        let received: Received = Arc::default();
+        let server_received = Arc::clone(&received);
+        let closed = Arc::new(AtomicBool::new(false));
+        tokio::spawn(async move {
+            while let Ok((tcp, _)) = listener.accept().await {
+                if closed.load(Ordering::Relaxed) {
+                    break;
+                }
+                let received = Arc::clone(&server_received);
+                let closed = Arc::clone(&closed);
+                tokio::spawn(async move {
+                    let mut builder = h2::server::Builder::new();
+                    if let Some(max) = max_concurrent_streams {
+                        builder.max_concurrent_streams(max);
+                    }
+                    let Ok(mut conn) = builder.handshake::<_, Bytes>(tcp).await else {
+                        return;
+                    };
+                    let mut unanswered = vec![];
+                    while let Some(Ok((request, mut respond))) = conn.accept().await {
+                        let path = request.uri().path().to_string();
+                        received
+                            .lock()
+                            .unwrap()
+                            .push((Instant::now(), path.clone()));
+                        if path == "/close" {
+                            closed.store(true, Ordering::Relaxed);
+                            return;
+                        }
+
+                        let mut parts = path.split('/').skip(1);
+                        let status = parts.next().and_then(|part| part.parse::<u16>().ok());
+                        let delay = parts.next().and_then(|part| part.parse().ok());
+                        let (Some(status), Some(delay)) = (status, delay) else {
+                            unanswered.push(respond);
+                            continue;
+                        };
+                        tokio::spawn(async move {
+                            time::sleep(Duration::from_millis(delay)).await;
+                            let response = Response::builder().status(status).body(()).unwrap();
+                            let _ = respond.send_response(response, true);
+                        });
+                    }
+                });
+            }
+        });
+
+        (port, received)
+    }
+
+    // Runner config of `yaml` sending to the test server
+    fn config(port: u16, yaml: &str) -> RunnerConfig {
+        let mut config: serde_yaml::Value = serde_yaml::from_str(yaml).unwrap();
+        let defaults = format!(
+            "{{ base_url: 'http://127.0.0.1:{}', batch_size: Auto, global: {{ variables: [] }} }}",
+            port
+        );
+        let serde_yaml::Value::Mapping(defaults) = serde_yaml::from_str(&defaults).unwrap() else {
+            unreachable!();
+        };
+        let mapping = config.as_mapping_mut().unwrap();
+        for (key, value) in defaults {
+            if !mapping.contains_key(&key) {
+                mapping.insert(key, value);
+            }
+        }
+        serde_yaml::from_value(config).unwrap()
+    }
+
+    // Runs a single runner, a run that does not end fails the test
+    async fn run(config: RunnerConfig) -> Result<(RunReport, Arc<ErrorBudget>), Box<dyn Error>> {
+        let budget = Arc::new(ErrorBudget::new(&config.assert)?);
+        let global = Global::new(config.global.clone());
+        let mut runner = Runner::new(config)?;
+        let run = runner.run(global, Arc::new(ApiStats::new()), None, Arc::clone(&budget));
+        let report = time::timeout(Duration::from_secs(10), run)
+            .await
+            .expect("Run did not end")?;
+        Ok((report, budget))
+    }
+
+    // Most requests the server got within `window`, a pair may arrive at
+    // once when the client's TCP segments are held back by Nagle
+    fn max_arrivals(received: &Received, window: Duration) -> usize {
+        let received = received.lock().unwrap();
+        received
+            .iter()
+            .map(|(start, _)| {
+                received
+                    .iter()
+                    .filter(|(at, _)| *at >= *start && *at < *start + window)
+                    .count()
+            })
+            .max()
+            .unwrap_or(0)
+    }
+
+    #[tokio::test]
+    async fn test_run_load_profile_stages() {
+        let (port, received) = start_server(None).await;
+        let config = config(
+            port,
+            r#"
+            target_rps: 0
+            duration: 0s
+            load_profile:
+              - duration: 500ms
+                target_rps: 20
+              - duration: 500ms
+                target_rps: 40
+            scenarios:
+              - name: get
+                request:
+                  method: GET
+                  path: /200/400
+                  timeout: 1s
+                response:
+                  assert:
+                    status: 200
+            "#,
+        );
+        let (report, _) = run(config).await.unwrap();
+
+        // 10 chains 50ms apart, then 20 chains 25ms apart
+        assert_eq!(report.success_count, 30);
+        assert_eq!(received.lock().unwrap().len(), 30);
+        // Stages follow each other without waiting for the chains in flight,
+        // nor sending a burst when the rate changes
+        assert!(report.elapsed < Duration::from_millis(1600));
+        assert!(max_arrivals(&received, Duration::from_millis(100)) <= 8);
+    }
+
+    #[test]
+    fn test_runner_reconnect_max_attempts() {
+        let yaml = r#"
+            target_rps: 10
+            duration: 1s
+            reconnect:
+              max_attempts: 0
+            scenarios:
+              - name: get
+                request:
+                  method: GET
+                  path: /200/0
+                  timeout: 1s
+                response:
+                  assert:
+                    status: 200
+            "#;
+        let e = Runner::new(config(8080, yaml)).err().unwrap();
+        assert_eq!(e.to_string(), "reconnect max_attempts must be at least 1");
+    }
+
+    #[tokio::test]
+    async fn test_run_capacity_search() {
+        // A single stream answered after 50ms, the server keeps up with 20 RPS
+        let (port, _) = start_server(Some(1)).await;
+        let config = config(
+            port,
+            r#"
+            target_rps: 0
+            duration: 1s
+            auto_throttle:
+              start_rps: 10
+              step_rps: 40
+              max_rps: 50
+              min_step_rps: 40
+              step_duration: 500ms
+              max_p99: 150ms
+            scenarios:
+              - name: get
+                request:
+                  method: GET
+                  path: /200/50
+                  timeout: 5s
+                response:
+                  assert:
+                    status: 200
+            "#,
+        );
+        let (report, _) = run(config).await.unwrap();
+
+        // At 50 RPS the round trips stay at 50ms, the sends queue up behind
+        // the single stream
+        assert_eq!(report.capacity_rps, Some(10));
+    }
+
+    #[tokio::test]
+    async fn test_run_concurrency() {
+        let (port, received) = start_server(None).await;
+        let config = config(
+            port,
+            r#"
+            target_rps: 0
+            duration: 1s
+            mode: concurrency
+            concurrency:
+              users: 2
+              think_time: 200ms
+            scenarios:
+              - name: get
+                request:
+                  method: GET
+                  path: /200/0
+                  timeout: 1s
+                response:
+                  assert:
+                    status: 200
+            "#,
+        );
+        let (report, _) = run(config).await.unwrap();
+
+        // Each user starts a chain about every 200ms, the last one just after 1s
+        assert_eq!(report.virtual_users, 2);
+        assert!((8..=12).contains(&report.success_count));
+        assert_eq!(received.lock().unwrap().len() as u32, report.success_count);
+        // Not a burst of chains, the users wait for their think time
+        assert!(max_arrivals(&received, Duration::from_millis(150)) <= 2);
+    }
+
+    #[tokio::test]
+    async fn test_run_backpressure_wait() {
+        // Two requests in flight, but the server only opens one stream at a
+        // time
+        let (port, received) = start_server(Some(1)).await;
+        let config = config(
+            port,
+            r#"
+            target_rps: 20
+            duration: 1s
+            max_in_flight: 2
+            backpressure: wait
+            scenarios:
+              - name: get
+                request:
+                  method: GET
+                  path: /200/100
+                  timeout: 5s
+                response:
+                  assert:
+                    status: 200
+            "#,
+        );
+        let (report, _) = run(config).await.unwrap();
+
+        assert_eq!(report.success_count, 20);
+        assert_eq!(received.lock().unwrap().len(), 20);
+        assert_eq!(report.dropped_count, 0);
+        // Sends wait for a permit or a stream, each is counted once
+        assert!(report.delayed_count > 0);
+        assert!(report.delayed_count <= report.success_count);
+    }
+
+    #[tokio::test]
+    async fn test_run_backpressure_drop() {
+        let (port, received) = start_server(None).await;
+        let config = config(
+            port,
+            r#"
+            target_rps: 20
+            duration: 1s
+            max_in_flight: 1
+            backpressure: drop
+            scenarios:
+              - name: get
+                request:
+                  method: GET
+                  path: /200/120
+                  timeout: 5s
+                response:
+                  assert:
+                    status: 200
+            "#,
+        );
+        let (report, _) = run(config).await.unwrap();
+
+        // A chain every 50ms, only one of three gets the permit
+        let sent = received.lock().unwrap().len() as u32;
+        assert!((6..=10).contains(&sent));
+        assert_eq!(report.success_count, sent);
+        assert_eq!(report.dropped_count, 20 - sent);
+        assert_eq!(report.delayed_count, 0);
+    }
+
+    #[test]
+    fn test_runner_backpressure_drop_in_concurrency_mode() {
+        let yaml = r#"
+            target_rps: 0
+            duration: 1s
+            mode: concurrency
+            concurrency:
+              users: 10
+            max_in_flight: 5
+            backpressure: drop
+            scenarios:
+              - name: get
+                request:
+                  method: GET
+                  path: /200/0
+                  timeout: 1s
+                response:
+                  assert:
+                    status: 200
+            "#;
+        let e = Runner::new(config(8080, yaml)).err().unwrap();
+        assert_eq!(
+            e.to_string(),
+            "backpressure drop is not supported in mode concurrency"
+        );
+        assert!(Runner::new(config(8080, &yaml.replace("drop", "wait"))).is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_run_corrected_response_time() {
+        // 100ms per request on a single stream, the server keeps up with
+        // half the target rate
+        let (port, _) = start_server(Some(1)).await;
+        let config = config(
+            port,
+            r#"
+            target_rps: 20
+            duration: 1s
+            scenarios:
+              - name: get
+                request:
+                  method: GET
+                  path: /200/100
+                  timeout: 5s
+                response:
+                  assert:
+                    status: 200
+            "#,
+        );
+        let (report, _) = run(config).await.unwrap();
+        assert_eq!(report.success_count, 20);
+
+        // The last requests were due long before they could be sent, that
+        // wait only shows in the response time
+        let rtt = Duration::from_micros(report.rtt_histogram.max());
+        let response_time = Duration::from_micros(report.response_time_histogram.max());
+        assert!(rtt < Duration::from_millis(400));
+        assert!(response_time > Duration::from_millis(700));
+    }
+
+    #[tokio::test]
+    async fn test_run_poisson_arrivals() {
+        let (port, received) = start_server(None).await;
+        let config = config(
+            port,
+            r#"
+            target_rps: 100
+            duration: 1s
+            arrival:
+              distribution: poisson
+            scenarios:
+              - name: get
+                request:
+                  method: GET
+                  path: /200/0
+                  timeout: 1s
+                response:
+                  assert:
+                    status: 200
+            "#,
+        );
+        let (report, _) = run(config).await.unwrap();
+
+        // Poisson(100) chains, within 4 standard deviations
+        assert!((60..=140).contains(&report.success_count));
+        assert_eq!(received.lock().unwrap().len() as u32, report.success_count);
+    }
+
+    #[tokio::test]
+    async fn test_run_repeat() {
+        let (port, received) = start_server(None).await;
+        let config = config(
+            port,
+            r#"
+            target_rps: 4
+            duration: 1s
+            scenarios:
+              - name: create
+                request:
+                  method: GET
+                  path: /201/0
+                  timeout: 1s
+                response:
+                  assert:
+                    status: 201
+              - name: update
+                repeat:
+                  count: 3
+                request:
+                  method: GET
+                  path: /200/0
+                  timeout: 1s
+                response:
+                  assert:
+                    status: 200
+            "#,
+        );
+        let (report, _) = run(config).await.unwrap();
+
+        // One chain of 4 requests per second
+        let paths: Vec<String> = received
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(_, path)| path.clone())
+            .collect();
+        assert_eq!(paths, ["/201/0", "/200/0", "/200/0", "/200/0"]);
+        assert_eq!(report.success_count, 4);
+        assert_eq!(report.scenario_stats[1].success, 3);
+    }
+
+    #[tokio::test]
+    async fn test_run_goto_jumps_cap() {
+        let (port, received) = start_server(None).await;
+        let config = config(
+            port,
+            r#"
+            target_rps: 1
+            duration: 1s
+            scenarios:
+              - name: poll
+                request:
+                  method: GET
+                  path: /200/0
+                  timeout: 1s
+                response:
+                  assert:
+                    status: 200
+                next:
+                  - when:
+                      status: 200
+                    goto: poll
+            "#,
+        );
+        let (report, _) = run(config).await.unwrap();
+
+        // The first request and one per jump, then the chain is ended
+        assert_eq!(received.lock().unwrap().len(), 101);
+        assert_eq!(report.error_count, 1);
+        assert_eq!(
+            report.scenario_stats[0].top_failures(1),
+            [("Chain ended after 100 goto jumps", 1)]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_run_first_scenario_delay() {
+        let (port, received) = start_server(None).await;
+        let config = config(
+            port,
+            r#"
+            target_rps: 1
+            duration: 1s
+            scenarios:
+              - name: get
+                delay: 300ms
+                request:
+                  method: GET
+                  path: /200/0
+                  timeout: 1s
+                response:
+                  assert:
+                    status: 200
+            "#,
+        );
+        let start = Instant::now();
+        let (report, _) = run(config).await.unwrap();
+
+        // The chain is started right away, its first request waits for the delay
+        let received = received.lock().unwrap();
+        assert_eq!(received.len(), 1);
+        assert!(received[0].0.duration_since(start) >= Duration::from_millis(300));
+        // Not counted in the response time
+        assert!(report.response_time_histogram.max() < 200_000);
+    }
+
+    #[tokio::test]
+    async fn test_run_script_failure() {
+        let (port, received) = start_server(None).await;
+        let config = config(
+            port,
+            r#"
+            target_rps: 5
+            duration: 1s
+            scenarios:
+              - name: get
+                request:
+                  method: GET
+                  path: /200/0
+                  timeout: 1s
+                response:
+                  assert:
+                    status: 200
+                post-script:
+                  variables:
+                    - name: COUNTER
+                      function:
+                        type: Plus
+                      args:
+                        - $MISSING
+                        - 1
+            "#,
+        );
+        let (report, _) = run(config).await.unwrap();
+
+        // Counted as failed like an assertion, the run goes on
+        assert_eq!(received.lock().unwrap().len(), 5);
+        assert_eq!(report.success_count, 0);
+        assert_eq!(report.error_count, 5);
+        assert_eq!(report.scenario_stats[0].top_failures(1)[0].1, 5);
+    }
+
+    #[tokio::test]
+    async fn test_run_error_budget_exceeded() {
+        let (port, received) = start_server(None).await;
+        let config = config(
+            port,
+            r#"
+            target_rps: 50
+            duration: 2s
+            assert:
+              max_failures: 5
+            scenarios:
+              - name: get
+                request:
+                  method: GET
+                  path: /200/0
+                  timeout: 1s
+                response:
+                  assert:
+                    status: 201
+            "#,
+        );
+        let (report, budget) = run(config).await.unwrap();
+
+        // No chain is started once 5 requests failed, the ones in flight
+        // still count
+        assert_eq!(
+            budget.exceeded(),
+            Some("5 failed requests, max_failures is 5")
+        );
+        assert!((5..10).contains(&report.error_count));
+        assert_eq!(received.lock().unwrap().len() as u32, report.error_count);
+        assert!(report.elapsed < Duration::from_secs(1));
+    }
+
+    #[tokio::test]
+    async fn test_run_lost_response() {
+        let (port, received) = start_server(None).await;
+        let config = config(
+            port,
+            r#"
+            target_rps: 5
+            duration: 1s
+            scenarios:
+              - name: get
+                request:
+                  method: GET
+                  path: /never
+                  timeout: 200ms
+                response:
+                  assert:
+                    status: 200
+            "#,
+        );
+        let (report, _) = run(config).await.unwrap();
+
+        // The run ends once the last request timed out
+        assert_eq!(received.lock().unwrap().len(), 5);
+        assert_eq!(report.error_count, 5);
+        assert_eq!(report.scenario_stats[0].timeout, 5);
+        assert!(report.elapsed < Duration::from_millis(1500));
+    }
+
+    #[tokio::test]
+    async fn test_run_reconnect_gave_up() {
+        let (port, _) = start_server(None).await;
+        let config = config(
+            port,
+            r#"
+            target_rps: 5
+            duration: 2s
+            reconnect:
+              max_attempts: 1
+              initial_backoff: 10ms
+            scenarios:
+              - name: get
+                request:
+                  method: GET
+                  path: /close
+                  timeout: 1s
+                response:
+                  assert:
+                    status: 200
+            "#,
+        );
+        let start = Instant::now();
+        let e = run(config).await.err().unwrap();
+
+        // The server is gone after the first request, the runner fails
+        // rather than sending to a closed connection until the end
+        assert_eq!(
+            e.to_string(),
+            format!(
+                "Event loop stopped: Connection error: Gave up reconnecting all 1 connection(s) to 127.0.0.1:{}",
+                port
+            )
+        );
+        assert!(start.elapsed() < Duration::from_secs(1));
     }
 }
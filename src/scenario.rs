@@ -1,16 +1,21 @@
 use crate::config;
 use crate::function;
+use crate::http_api::ErrorKind;
 use crate::http_api::HttpRequest;
 use crate::http_api::HttpResponse;
 use crate::script;
 use crate::script::ScriptContext;
 use crate::variable::Value;
+use bytes::Bytes;
 use http::Method;
 use http::StatusCode;
+use rand::Rng;
 use regex::Regex;
 use serde::Deserialize;
 use serde::Serialize;
 use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
 use std::time::Duration;
 
 #[derive(Clone)]
@@ -19,17 +24,147 @@ pub struct Request {
     pub uri_var_name: Vec<String>,
     pub method: Method,
     pub headers: Option<Vec<HashMap<String, String>>>,
-    pub body: Option<String>,
+    // Variables found in header names and values
+    pub headers_var_name: Vec<String>,
+    pub body: Option<RequestBody>,
+    // Variables found in the templated parts of the body
     pub body_var_name: Vec<String>,
-    // pub body: Option<serde_json::Value>,
     pub timeout: Duration,
 }
 
+#[derive(Clone)]
+pub enum RequestBody {
+    Json(String),
+    Text(String),
+    // URL encoded, names and values are templated
+    Form(Vec<(String, String)>),
+    // Read from a file once, when the scenario is created
+    Binary(Bytes),
+    // multipart/related, the body of each part is `Text` or `Binary`
+    Multipart(Vec<MultipartPart>),
+}
+
+#[derive(Clone)]
+pub struct MultipartPart {
+    pub content_type: String,
+    pub content_id: Option<String>,
+    pub body: RequestBody,
+}
+
+// Separates the parts of multipart bodies
+const MULTIPART_BOUNDARY: &str = "h2load-boundary-6b1e2f04c9d7";
+
+impl RequestBody {
+    // Texts where variables are replaced
+    fn templates(&self) -> Vec<&str> {
+        match self {
+            RequestBody::Json(body) | RequestBody::Text(body) => vec![body],
+            RequestBody::Form(fields) => fields
+                .iter()
+                .flat_map(|(name, value)| [name.as_str(), value.as_str()])
+                .collect(),
+            RequestBody::Binary(_) => vec![],
+            RequestBody::Multipart(parts) => parts
+                .iter()
+                .flat_map(|part| part.body.templates())
+                .collect(),
+        }
+    }
+
+    fn content_type(&self) -> Option<String> {
+        match self {
+            RequestBody::Form(_) => Some("application/x-www-form-urlencoded".into()),
+            RequestBody::Multipart(parts) => Some(format!(
+                "multipart/related; boundary={}; type=\"{}\"",
+                MULTIPART_BOUNDARY, parts[0].content_type
+            )),
+            _ => None,
+        }
+    }
+
+    fn render(&self, var_names: &[String], ctx: &ScriptContext) -> Result<Bytes, Box<dyn Error>> {
+        let replace = |template: &str| Scenario::replace_variables(template, var_names, ctx);
+        let body = match self {
+            RequestBody::Json(body) => {
+                // Checked and sent compact
+                let body: serde_json::Value = serde_json::from_str(&replace(body)?)?;
+                serde_json::to_vec(&body)?.into()
+            }
+            RequestBody::Text(body) => replace(body)?.into(),
+            RequestBody::Form(fields) => {
+                let mut pairs = vec![];
+                for (name, value) in fields {
+                    pairs.push(format!(
+                        "{}={}",
+                        form_urlencode(&replace(name)?),
+                        form_urlencode(&replace(value)?)
+                    ));
+                }
+                pairs.join("&").into()
+            }
+            RequestBody::Binary(body) => body.clone(),
+            RequestBody::Multipart(parts) => {
+                let mut body = vec![];
+                for part in parts {
+                    body.extend_from_slice(format!("--{}\r\n", MULTIPART_BOUNDARY).as_bytes());
+                    body.extend_from_slice(
+                        format!("Content-Type: {}\r\n", part.content_type).as_bytes(),
+                    );
+                    if let Some(content_id) = &part.content_id {
+                        body.extend_from_slice(
+                            format!("Content-Id: {}\r\n", content_id).as_bytes(),
+                        );
+                    }
+                    body.extend_from_slice(b"\r\n");
+                    body.extend_from_slice(&part.body.render(var_names, ctx)?);
+                    body.extend_from_slice(b"\r\n");
+                }
+                body.extend_from_slice(format!("--{}--\r\n", MULTIPART_BOUNDARY).as_bytes());
+                body.into()
+            }
+        };
+        Ok(body)
+    }
+}
+
+// application/x-www-form-urlencoded encoding of a name or value
+fn form_urlencode(s: &str) -> String {
+    let mut encoded = String::new();
+    for byte in s.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'*' => {
+                encoded.push(byte as char)
+            }
+            b' ' => encoded.push('+'),
+            _ => encoded.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    encoded
+}
+
 #[derive(Clone)]
 pub struct Response {
     pub status: http::StatusCode,
     pub headers: Option<Vec<HeadersAssert>>,
     pub body: Option<Vec<BodyAssert>>,
+    pub body_regex: Option<regex::bytes::Regex>,
+    pub body_length: Option<BodyLength>,
+}
+
+#[derive(Debug, Deserialize, Serialize, PartialEq, Clone)]
+#[serde(untagged)]
+pub enum BodyLength {
+    Fixed(usize),
+    Range { min: usize, max: usize },
+}
+
+impl fmt::Display for BodyLength {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BodyLength::Fixed(length) => write!(f, "{}", length),
+            BodyLength::Range { min, max } => write!(f, "between {} and {}", min, max),
+        }
+    }
 }
 
 #[derive(Debug, Deserialize, Serialize, Clone)]
@@ -71,6 +206,158 @@ pub struct ResponseDefine {
 pub enum DefineFrom {
     Header,
     Body,
+    // `path` is a regex on the raw body, the first group or the whole match
+    // is taken
+    Regex,
+}
+
+// Iterations of an `until` repeat without a count
+const DEFAULT_MAX_REPEAT: u32 = 100;
+
+// Sends the scenario several times in a row with the chain's context, `count`
+// times, or until the `until` condition holds after an iteration, `count`
+// then being the most iterations
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct Repeat {
+    pub count: Option<RepeatCount>,
+    pub until: Option<VariableAssert>,
+}
+
+#[derive(Debug, Deserialize, Serialize, PartialEq, Clone)]
+#[serde(untagged)]
+pub enum RepeatCount {
+    Fixed(u32),
+    // Drawn each time the chain reaches the scenario
+    Range { min: u32, max: u32 },
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct VariableAssert {
+    pub name: String,
+    pub value: VariableValueAssert,
+}
+
+#[derive(Debug, Deserialize, Serialize, PartialEq, Clone)]
+#[serde(tag = "type", content = "value")]
+pub enum VariableValueAssert {
+    NotNull,
+    Equal(Value),
+    NotEqual(Value),
+}
+
+impl Repeat {
+    pub fn validate(&self, scenario: &str) -> Result<(), Box<dyn Error>> {
+        let valid = match &self.count {
+            Some(RepeatCount::Fixed(count)) => *count > 0,
+            Some(RepeatCount::Range { min, max }) => *min > 0 && min <= max,
+            None => self.until.is_some(),
+        };
+        if !valid {
+            return Err(format!(
+                "Repeat of scenario '{}' needs a count of at least 1 or an until condition",
+                scenario
+            )
+            .into());
+        }
+        Ok(())
+    }
+
+    pub fn iterations(&self) -> u32 {
+        match &self.count {
+            Some(RepeatCount::Fixed(count)) => *count,
+            Some(RepeatCount::Range { min, max }) => rand::thread_rng().gen_range(*min..=*max),
+            None => DEFAULT_MAX_REPEAT,
+        }
+    }
+
+    // Average requests sent, an `until` repeat is counted once as it depends
+    // on the responses
+    pub fn expected_requests(&self) -> f64 {
+        if self.until.is_some() {
+            return 1.0;
+        }
+        match &self.count {
+            Some(RepeatCount::Fixed(count)) => *count as f64,
+            Some(RepeatCount::Range { min, max }) => (*min + *max) as f64 / 2.0,
+            None => 1.0,
+        }
+    }
+
+    pub fn is_done(&self, ctx: &ScriptContext) -> bool {
+        match &self.until {
+            Some(until) => until.check(ctx),
+            None => false,
+        }
+    }
+}
+
+impl VariableAssert {
+    pub fn check(&self, ctx: &ScriptContext) -> bool {
+        let value = ctx.get_variable(&self.name);
+        match &self.value {
+            VariableValueAssert::NotNull => value.is_some(),
+            VariableValueAssert::Equal(expected) => {
+                value.is_some_and(|v| v.as_string() == expected.as_string())
+            }
+            VariableValueAssert::NotEqual(expected) => {
+                value.is_none_or(|v| v.as_string() != expected.as_string())
+            }
+        }
+    }
+}
+
+// Step taken after a response when all the conditions of `when` hold, jump to
+// the scenario named by `goto`, `end` the chain, or neither to go on as usual
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct NextRule {
+    pub when: NextCondition,
+    pub goto: Option<String>,
+    #[serde(default)]
+    pub end: bool,
+}
+
+#[derive(Debug, Deserialize, Serialize, Default, Clone)]
+pub struct NextCondition {
+    pub status: Option<u16>,
+    pub assert: Option<AssertOutcome>,
+    pub variable: Option<VariableAssert>,
+    // Only matches requests that failed without a response
+    pub error: Option<ErrorKind>,
+}
+
+#[derive(Debug, Deserialize, Serialize, PartialEq, Copy, Clone)]
+#[serde(rename_all = "lowercase")]
+pub enum AssertOutcome {
+    Passed,
+    Failed,
+}
+
+impl NextCondition {
+    pub fn matches(&self, status: StatusCode, passed: bool, ctx: &ScriptContext) -> bool {
+        if self.error.is_some() {
+            return false;
+        }
+        if self.status.is_some_and(|s| s != status.as_u16()) {
+            return false;
+        }
+        if let Some(outcome) = self.assert {
+            if (outcome == AssertOutcome::Passed) != passed {
+                return false;
+            }
+        }
+        self.variable
+            .as_ref()
+            .is_none_or(|variable| variable.check(ctx))
+    }
+
+    pub fn matches_error(&self, kind: ErrorKind, ctx: &ScriptContext) -> bool {
+        if self.error != Some(kind) || self.status.is_some() || self.assert.is_some() {
+            return false;
+        }
+        self.variable
+            .as_ref()
+            .is_none_or(|variable| variable.check(ctx))
+    }
 }
 
 // #[derive(Clone)]
@@ -80,28 +367,56 @@ pub struct Scenario {
     pub request: Request,
     pub response: Response,
     pub response_defines: Vec<ResponseDefine>,
-    pub assert_panic: bool,
+    // Compiled `Regex` defines by pattern
+    pub define_regexes: HashMap<String, regex::bytes::Regex>,
     pub pre_script: Option<Vec<script::Script>>,
     pub post_script: Option<Vec<script::Script>>,
+    pub repeat: Option<Repeat>,
+    pub delay: Option<config::Delay>,
 }
 
 impl Scenario {
-    pub fn new(config: &config::Scenario, base_url: &str) -> Self {
+    pub fn new(config: &config::Scenario, base_url: &str) -> Result<Self, Box<dyn Error>> {
         // Find variables in body and url
-        let body_var_name =
-            Scenario::find_variable_name(&config.request.body.clone().unwrap_or_default());
+        let body = Scenario::request_body(&config.name, &config.request)?;
+        let mut body_var_name = vec![];
+        for var_name in body
+            .iter()
+            .flat_map(|body| body.templates())
+            .flat_map(Scenario::find_variable_name)
+        {
+            if !body_var_name.contains(&var_name) {
+                body_var_name.push(var_name);
+            }
+        }
         let uri_var_name = Scenario::find_variable_name(&config.request.path);
+        let mut headers_var_name = vec![];
+        for header in config.request.headers.iter().flatten() {
+            for var_name in header
+                .iter()
+                .flat_map(|(name, value)| [name, value])
+                .flat_map(|s| Scenario::find_variable_name(s))
+            {
+                if !headers_var_name.contains(&var_name) {
+                    headers_var_name.push(var_name);
+                }
+            }
+        }
 
         //Local Variable
         let mut response_defines = vec![];
-        match &config.response.define {
-            Some(define) => {
-                for v in define {
-                    let response_define = v.clone();
-                    response_defines.push(response_define);
+        let mut define_regexes = HashMap::new();
+        if let Some(define) = &config.response.define {
+            for v in define {
+                if v.from == DefineFrom::Regex {
+                    let regex = regex::bytes::Regex::new(&v.path).map_err(|e| {
+                        format!("Define '{}' of scenario '{}': {}", v.name, config.name, e)
+                    })?;
+                    define_regexes.insert(v.path.clone(), regex);
                 }
+                let response_define = v.clone();
+                response_defines.push(response_define);
             }
-            None => {}
         }
 
         // Requets
@@ -110,7 +425,8 @@ impl Scenario {
             uri_var_name,
             method: config.request.method.parse().unwrap(),
             headers: config.request.headers.clone(),
-            body: config.request.body.clone(),
+            headers_var_name,
+            body,
             body_var_name,
             timeout: config.request.timeout,
         };
@@ -120,6 +436,14 @@ impl Scenario {
             status: StatusCode::from_u16(config.response.assert.status).unwrap(),
             headers: config.response.assert.headers.clone(),
             body: config.response.assert.body.clone(),
+            body_regex: match &config.response.assert.body_regex {
+                Some(pattern) => Some(
+                    regex::bytes::Regex::new(pattern)
+                        .map_err(|e| format!("Body regex of scenario '{}': {}", config.name, e))?,
+                ),
+                None => None,
+            },
+            body_length: config.response.assert.body_length.clone(),
         };
 
         let pre_script = match &config.pre_script {
@@ -146,16 +470,126 @@ impl Scenario {
             None => None,
         };
 
-        Scenario {
+        Ok(Scenario {
             name: config.name.clone(),
             base_url: base_url.into(),
             request,
             response,
             response_defines,
-            assert_panic: true,
+            define_regexes,
             pre_script,
             post_script,
+            repeat: config.repeat.clone(),
+            delay: config.delay.clone(),
+        })
+    }
+
+    // Files of the body are read here, once
+    fn request_body(
+        name: &str,
+        config: &config::Request,
+    ) -> Result<Option<RequestBody>, Box<dyn Error>> {
+        let set = [
+            config.body.is_some(),
+            config.form.is_some(),
+            config.body_file.is_some(),
+            config.multipart.is_some(),
+        ];
+        if set.iter().filter(|set| **set).count() > 1 {
+            return Err(format!(
+                "Scenario '{}' can only have one of body, form, body_file or multipart",
+                name
+            )
+            .into());
+        }
+
+        let read_file = |path: &str| -> Result<RequestBody, Box<dyn Error>> {
+            let content = std::fs::read(path)
+                .map_err(|e| format!("Scenario '{}' body file '{}': {}", name, path, e))?;
+            Ok(RequestBody::Binary(content.into()))
+        };
+
+        let body = if let Some(body) = &config.body {
+            match config.body_type {
+                config::BodyType::Json => RequestBody::Json(body.clone()),
+                config::BodyType::Text => RequestBody::Text(body.clone()),
+            }
+        } else if let Some(form) = &config.form {
+            RequestBody::Form(
+                form.iter()
+                    .map(|field| (field.name.clone(), field.value.clone()))
+                    .collect(),
+            )
+        } else if let Some(path) = &config.body_file {
+            read_file(path)?
+        } else if let Some(parts) = &config.multipart {
+            if parts.is_empty() {
+                return Err(format!("Multipart body of scenario '{}' has no part", name).into());
+            }
+            let mut multipart = vec![];
+            for part in parts {
+                let body = match (&part.body, &part.file) {
+                    (Some(body), None) => RequestBody::Text(body.clone()),
+                    (None, Some(path)) => read_file(path)?,
+                    _ => {
+                        return Err(format!(
+                            "Multipart part '{}' of scenario '{}' needs either a body or a file",
+                            part.content_type, name
+                        )
+                        .into())
+                    }
+                };
+                multipart.push(MultipartPart {
+                    content_type: part.content_type.clone(),
+                    content_id: part.content_id.clone(),
+                    body,
+                });
+            }
+            RequestBody::Multipart(multipart)
+        } else {
+            return Ok(None);
+        };
+        Ok(Some(body))
+    }
+
+    // Times to send the scenario each time the chain reaches it
+    pub fn repeat_iterations(&self) -> u32 {
+        self.repeat.as_ref().map_or(1, |repeat| repeat.iterations())
+    }
+
+    // Whether the `until` condition of the repeat holds
+    pub fn repeat_done(&self, ctx: &ScriptContext) -> bool {
+        self.repeat
+            .as_ref()
+            .is_some_and(|repeat| repeat.is_done(ctx))
+    }
+
+    // Wait before sending the scenario, drawn each time the chain reaches it
+    pub fn delay(&self) -> Duration {
+        self.delay
+            .as_ref()
+            .map_or(Duration::ZERO, |delay| delay.sample())
+    }
+
+    // Replaces `${name}` with the value of each variable found in `template`
+    fn replace_variables(
+        template: &str,
+        var_names: &[String],
+        ctx: &ScriptContext,
+    ) -> Result<String, Box<dyn Error>> {
+        let mut result = template.to_string();
+        for name in var_names {
+            let placeholder = format!("${{{}}}", name);
+            if !result.contains(&placeholder) {
+                continue;
+            }
+            let value = match ctx.must_get_variable(name)? {
+                Value::Int(v) => v.to_string(),
+                Value::String(v) => v,
+            };
+            result = result.replace(&placeholder, &value);
         }
+        Ok(result)
     }
 
     fn find_variable_name(str: &str) -> Vec<String> {
@@ -173,37 +607,59 @@ impl Scenario {
         ctx: &ScriptContext,
     ) -> Result<HttpRequest, Box<dyn std::error::Error>> {
         let body = match &self.request.body {
-            Some(body) => {
-                let mut body = body.clone();
-
-                // Apply vairables replace in body
-                for name in &self.request.body_var_name {
-                    let value = ctx.must_get_variable(&name)?;
-                    let value = match value {
-                        Value::Int(v) => v.to_string(),
-                        Value::String(v) => v,
-                    };
-                    body = body.replace(&format!("${{{}}}", name), &value);
-                }
+            Some(body) => Some(body.render(&self.request.body_var_name, ctx)?),
+            None => None,
+        };
 
-                Some(serde_json::from_str(&body).unwrap())
+        let uri = Scenario::replace_variables(&self.request.uri, &self.request.uri_var_name, ctx)?;
+
+        let headers = match &self.request.headers {
+            Some(headers) => {
+                let mut replaced = vec![];
+                for header in headers {
+                    let mut map = HashMap::new();
+                    for (name, value) in header {
+                        let var_names = &self.request.headers_var_name;
+                        let name = Scenario::replace_variables(name, var_names, ctx)?;
+                        let value = Scenario::replace_variables(value, var_names, ctx)?;
+
+                        // Fail here rather than when the request is sent
+                        http::HeaderName::from_bytes(name.as_bytes())
+                            .map_err(|e| format!("Header '{}': {}", name, e))?;
+                        http::HeaderValue::from_str(&value)
+                            .map_err(|e| format!("Header '{}' value '{}': {}", name, value, e))?;
+                        map.insert(name, value);
+                    }
+                    replaced.push(map);
+                }
+                Some(replaced)
             }
             None => None,
         };
 
-        let uri = {
-            let mut uri = self.request.uri.clone();
-
-            // Apply vairables replace in uri
-            for name in &self.request.uri_var_name {
-                let value = ctx.must_get_variable(&name)?;
-                let value = match value {
-                    Value::Int(v) => v.to_string(),
-                    Value::String(v) => v,
-                };
-                uri = uri.replace(&format!("${{{}}}", name), &value);
+        // Form and multipart bodies bring their content type, multipart
+        // always as it holds the boundary
+        let content_type = self
+            .request
+            .body
+            .as_ref()
+            .and_then(|body| body.content_type());
+        let headers = match content_type {
+            Some(content_type) => {
+                let mut headers = headers.unwrap_or_default();
+                let is_content_type = |name: &String| name.eq_ignore_ascii_case("content-type");
+                let is_set = headers.iter().flat_map(|h| h.keys()).any(is_content_type);
+                if let Some(RequestBody::Multipart(_)) = &self.request.body {
+                    for header in headers.iter_mut() {
+                        header.retain(|name, _| !is_content_type(name));
+                    }
+                    headers.push(HashMap::from([("content-type".into(), content_type)]));
+                } else if !is_set {
+                    headers.push(HashMap::from([("content-type".into(), content_type)]));
+                }
+                Some(headers)
             }
-            uri
+            None => headers,
         };
 
         // Add base_url to uri
@@ -212,24 +668,15 @@ impl Scenario {
         Ok(HttpRequest {
             uri,
             method: self.request.method.clone(),
-            headers: self.request.headers.clone(),
+            headers,
             body,
-            timeout: self.request.timeout.clone(),
+            timeout: self.request.timeout,
         })
     }
 
-    pub fn assert_response(&self, response: &HttpResponse) -> bool {
-        match self.check_response(response) {
-            Ok(_) => true,
-            Err(err) => {
-                if self.assert_panic {
-                    panic!("{}", err);
-                } else {
-                    log::error!("{}", err);
-                }
-                false
-            }
-        }
+    // Reason of the failed assertion as the error
+    pub fn assert_response(&self, response: &HttpResponse) -> Result<(), String> {
+        self.check_response(response).map_err(|e| e.to_string())
     }
 
     fn check_response(&self, response: &HttpResponse) -> Result<(), Box<dyn std::error::Error>> {
@@ -243,14 +690,15 @@ impl Scenario {
         }
 
         // Check Headers
-        if self.response.headers.is_some() {
-            let headers = self.response.headers.as_ref().unwrap();
+        if let Some(headers) = &self.response.headers {
             for h in headers {
                 let value = h.value.clone();
                 let header = response
                     .headers
                     .get(&h.name)
-                    .map(|hdr| hdr.to_str().unwrap());
+                    .map(|hdr| hdr.to_str())
+                    .transpose()
+                    .map_err(|_| format!("Header '{}' is not valid text", h.name))?;
 
                 match value {
                     HeadersValueAssert::NotNull => {
@@ -281,14 +729,11 @@ impl Scenario {
         }
 
         // Check Body
-        if self.response.body.is_some() {
-            let body_assert = self.response.body.as_ref().unwrap();
-
-            let body = response.body.as_ref();
-            if body == None {
-                return Err("Body is expected but not found".into());
-            }
-            let body = body.unwrap();
+        if let Some(body_assert) = &self.response.body {
+            let body = match response.body.as_ref() {
+                Some(body) => body,
+                None => return Err("Body is expected but not found".into()),
+            };
 
             for b in body_assert {
                 // Support nested json
@@ -330,12 +775,17 @@ impl Scenario {
                 match value_assert {
                     BodyValueAssert::NotNull => {}
                     BodyValueAssert::EqualString(v) => {
-                        if value.as_str().unwrap() != v {
+                        let Some(s) = value.as_str() else {
+                            return Err(format!(
+                                "Body '{}' is expected to be a string but got '{}'",
+                                b.name, value
+                            )
+                            .into());
+                        };
+                        if s != v {
                             return Err(format!(
                                 "Body '{}' is expected to be '{}' but got '{}'",
-                                b.name,
-                                v,
-                                value.as_str().unwrap()
+                                b.name, v, s
                             )
                             .into());
                         }
@@ -371,9 +821,29 @@ impl Scenario {
             }
         }
 
-        return Ok(());
+        // Check raw Body, whatever the content type
+        if let Some(regex) = &self.response.body_regex {
+            if !regex.is_match(&response.raw_body) {
+                return Err(format!("Body is expected to match '{}'", regex.as_str()).into());
+            }
+        }
+        if let Some(length) = &self.response.body_length {
+            let len = response.raw_body.len();
+            let valid = match length {
+                BodyLength::Fixed(expected) => len == *expected,
+                BodyLength::Range { min, max } => (*min..=*max).contains(&len),
+            };
+            if !valid {
+                return Err(
+                    format!("Body length is expected to be {} but got {}", length, len).into(),
+                );
+            }
+        }
+
+        Ok(())
     }
 
+    #[allow(clippy::wrong_self_convention)]
     pub fn from_response(
         &self,
         ctx: &mut ScriptContext,
@@ -384,7 +854,9 @@ impl Scenario {
                 DefineFrom::Header => {
                     let headers = &response.headers;
                     if let Some(header) = headers.get(&v.path) {
-                        let value = header.to_str().unwrap();
+                        let value = header.to_str().map_err(|_| {
+                            format!("Define '{}': header '{}' is not valid text", v.name, v.path)
+                        })?;
                         log::debug!(
                             "Set local var from header: '{}', name: '{}' value: '{}'",
                             v.path,
@@ -397,8 +869,12 @@ impl Scenario {
                 }
                 DefineFrom::Body => {
                     if let Some(body) = &response.body {
-                        let value = jsonpath_lib::select(&body, &v.path).unwrap();
-                        let value = value.get(0).unwrap();
+                        let values = jsonpath_lib::select(body, &v.path).map_err(|e| {
+                            format!("Define '{}': invalid path '{}': {:?}", v.name, v.path, e)
+                        })?;
+                        let value = values.first().ok_or_else(|| {
+                            format!("Define '{}': '{}' not found in body", v.name, v.path)
+                        })?;
 
                         log::debug!(
                             "Set local var from json field: '{}', name: '{}' value: '{}'",
@@ -407,28 +883,53 @@ impl Scenario {
                             value,
                         );
 
-                        let value = if value.is_f64() {
-                            Value::Int(value.as_f64().unwrap() as i32)
-                        } else if value.is_i64() {
-                            Value::Int(value.as_i64().unwrap() as i32)
+                        let value = if let Some(value) = value.as_i64() {
+                            Value::Int(value as i32)
+                        } else if let Some(value) = value.as_f64() {
+                            Value::Int(value as i32)
+                        } else if let Some(value) = value.as_str() {
+                            Value::String(value.to_string())
                         } else {
-                            Value::String(value.as_str().unwrap().to_string())
+                            return Err(format!(
+                                "Define '{}': '{}' is {}, expected a number or a string",
+                                v.name, v.path, value
+                            )
+                            .into());
                         };
 
                         ctx.set_variable(&v.name, value);
                     }
                 }
+                DefineFrom::Regex => {
+                    let regex = self
+                        .define_regexes
+                        .get(&v.path)
+                        .ok_or_else(|| format!("Regex '{}' is not compiled", v.path))?;
+                    if let Some(caps) = regex.captures(&response.raw_body) {
+                        let Some(value) = caps.get(1).or_else(|| caps.get(0)) else {
+                            continue;
+                        };
+                        let value = String::from_utf8_lossy(value.as_bytes()).to_string();
+                        log::debug!(
+                            "Set local var from body regex: '{}', name: '{}' value: '{}'",
+                            v.path,
+                            v.name,
+                            value,
+                        );
+                        ctx.set_variable(&v.name, Value::String(value));
+                    }
+                }
             }
         }
         Ok(())
     }
 
-    pub fn run_pre_script(&self, ctx: &mut ScriptContext) {
+    pub fn run_pre_script(&self, ctx: &mut ScriptContext) -> Result<(), Box<dyn Error>> {
         log::debug!("run_pre_script");
 
         if let Some(script) = &self.pre_script {
             for s in script {
-                s.execute(ctx).unwrap();
+                s.execute(ctx)?;
             }
         }
 
@@ -436,14 +937,15 @@ impl Scenario {
         for (k, v) in ctx.local.variables.iter() {
             log::debug!("pre context variable: {} = {:?}", k, v);
         }
+        Ok(())
     }
 
-    pub fn run_post_script(&self, ctx: &mut ScriptContext) {
+    pub fn run_post_script(&self, ctx: &mut ScriptContext) -> Result<(), Box<dyn Error>> {
         log::debug!("run_post_script");
 
         if let Some(script) = &self.post_script {
             for s in script {
-                s.execute(ctx).unwrap();
+                s.execute(ctx)?;
             }
         }
 
@@ -451,6 +953,7 @@ impl Scenario {
         for (k, v) in ctx.local.variables.iter() {
             log::debug!("post context variable: {} = {:?}", k, v);
         }
+        Ok(())
     }
 }
 
@@ -496,11 +999,14 @@ mod tests {
 
         let mut headers = HashMap::new();
         headers.insert("Content-Type".to_string(), "application/json".to_string());
+        headers.insert("Authorization".to_string(), "Bearer ${token}".to_string());
+        headers.insert("3gpp-Sbi-${info}".to_string(), "imsi-${foo_id}".to_string());
 
         let body = r#"{"test": "${var1}_${var2}"}"#;
         let uri = "/endpoint/foo/${foo_id}";
-        let body_var_name = Scenario::find_variable_name(&body);
-        let uri_var_name = Scenario::find_variable_name(&uri);
+        let body_var_name = Scenario::find_variable_name(body);
+        let uri_var_name = Scenario::find_variable_name(uri);
+        let headers_var_name = vec!["token".into(), "info".into(), "foo_id".into()];
 
         let mut scenario = Scenario {
             name: "Scenario_1".into(),
@@ -510,7 +1016,8 @@ mod tests {
                 uri_var_name,
                 method: Method::GET,
                 headers: Some(vec![headers]),
-                body: Some(body.into()),
+                headers_var_name,
+                body: Some(RequestBody::Json(body.into())),
                 body_var_name,
                 timeout: Duration::from_secs(3),
             },
@@ -518,11 +1025,15 @@ mod tests {
                 status: StatusCode::OK,
                 headers: None,
                 body: None,
+                body_regex: None,
+                body_length: None,
             },
             response_defines: vec![],
-            assert_panic: false,
             pre_script: None,
             post_script: None,
+            repeat: None,
+            delay: None,
+            define_regexes: HashMap::new(),
         };
 
         let mut ctx = ScriptContext::new(global);
@@ -530,13 +1041,28 @@ mod tests {
         ctx.set_variable("var2", Value::Int(100));
         ctx.set_variable("foo_id", Value::String("1-2-3-4".into()));
 
+        ctx.set_variable("info", Value::String("Correlation-Info".into()));
+
+        // Same error as a variable missing in the body
+        let err = scenario.new_request(&ctx).err().unwrap();
+        assert_eq!(err.to_string(), "Script error: Variable 'token' not found");
+
+        ctx.set_variable("token", Value::String("abc".into()));
+        let request = scenario.new_request(&ctx).unwrap();
+        let headers = &request.headers.as_ref().unwrap()[0];
+        assert_eq!(headers["Authorization"], "Bearer abc");
+        assert_eq!(headers["3gpp-Sbi-Correlation-Info"], "imsi-1-2-3-4");
+        assert_eq!(headers["Content-Type"], "application/json");
+
+        ctx.set_variable("token", Value::String("a\nb".into()));
+        assert!(scenario.new_request(&ctx).is_err());
+        ctx.set_variable("token", Value::String("abc".into()));
+
         let request = scenario.new_request(&ctx).unwrap();
         assert_eq!(request.uri, "http://localhost:8080/endpoint/foo/1-2-3-4");
         assert_eq!(request.method, Method::GET);
-        assert_eq!(
-            request.body,
-            Some(serde_json::from_str(r#"{"test": "0_100"}"#).unwrap())
-        );
+        let body: serde_json::Value = serde_json::from_slice(&request.body.unwrap()).unwrap();
+        assert_eq!(body, serde_json::json!({"test": "0_100"}));
     }
 
     #[test]
@@ -549,6 +1075,7 @@ mod tests {
                 uri_var_name: vec![],
                 method: Method::GET,
                 headers: None,
+                headers_var_name: vec![],
                 body: None,
                 body_var_name: vec![],
                 timeout: Duration::from_secs(3),
@@ -557,11 +1084,15 @@ mod tests {
                 status: StatusCode::OK,
                 headers: None,
                 body: None,
+                body_regex: None,
+                body_length: None,
             },
             response_defines: vec![],
-            assert_panic: false,
             pre_script: None,
             post_script: None,
+            repeat: None,
+            delay: None,
+            define_regexes: HashMap::new(),
         };
 
         let response1 = HttpResponse {
@@ -570,6 +1101,7 @@ mod tests {
             body: None,
             request_start: std::time::Instant::now(),
             retry_count: 0,
+            raw_body: Bytes::new(),
         };
 
         let response2 = HttpResponse {
@@ -578,10 +1110,14 @@ mod tests {
             body: None,
             request_start: std::time::Instant::now(),
             retry_count: 0,
+            raw_body: Bytes::new(),
         };
 
-        assert_eq!(true, scenario.assert_response(&response1));
-        assert_eq!(false, scenario.assert_response(&response2));
+        assert!(scenario.assert_response(&response1).is_ok());
+        assert_eq!(
+            scenario.assert_response(&response2),
+            Err("Expected status code: 200, got: 404".to_string())
+        );
     }
 
     #[test]
@@ -594,6 +1130,7 @@ mod tests {
                 uri_var_name: vec![],
                 method: Method::GET,
                 headers: None,
+                headers_var_name: vec![],
                 body: None,
                 body_var_name: vec![],
                 timeout: Duration::from_secs(3),
@@ -608,11 +1145,15 @@ mod tests {
                     name: "Result".into(),
                     value: BodyValueAssert::EqualNumber(0.0),
                 }]),
+                body_regex: None,
+                body_length: None,
             },
             response_defines: vec![],
-            assert_panic: false,
             pre_script: None,
             post_script: None,
+            repeat: None,
+            delay: None,
+            define_regexes: HashMap::new(),
         };
 
         // Missing content-type header
@@ -622,6 +1163,7 @@ mod tests {
             body: None,
             request_start: std::time::Instant::now(),
             retry_count: 0,
+            raw_body: Bytes::new(),
         };
 
         match scenario.check_response(&response) {
@@ -642,6 +1184,7 @@ mod tests {
             body: None,
             request_start: std::time::Instant::now(),
             retry_count: 0,
+            raw_body: Bytes::new(),
         };
 
         match scenario.check_response(&response) {
@@ -656,6 +1199,7 @@ mod tests {
             body: Some(serde_json::from_str(r#"{"ObjectId": "0-1-2-3"}"#).unwrap()),
             request_start: std::time::Instant::now(),
             retry_count: 0,
+            raw_body: Bytes::new(),
         };
 
         match scenario.check_response(&response) {
@@ -670,6 +1214,7 @@ mod tests {
             body: Some(serde_json::from_str(r#"{"Result": 1, "ObjectId": "0-1-2-3"}"#).unwrap()),
             request_start: std::time::Instant::now(),
             retry_count: 0,
+            raw_body: Bytes::new(),
         };
 
         match scenario.check_response(&response) {
@@ -687,12 +1232,49 @@ mod tests {
             body: Some(serde_json::from_str(r#"{"Result": 0, "ObjectId": "0-1-2-3"}"#).unwrap()),
             request_start: std::time::Instant::now(),
             retry_count: 0,
+            raw_body: Bytes::new(),
         };
 
         match scenario.check_response(&response) {
             Ok(_) => {}
             Err(err) => panic!("{}", err),
         }
+
+        // Number in the body where a string is expected
+        let mut scenario = scenario;
+        scenario.response.body = Some(vec![BodyAssert {
+            name: "Result".into(),
+            value: BodyValueAssert::EqualString("0".into()),
+        }]);
+
+        match scenario.check_response(&response) {
+            Ok(_) => panic!("Expected error"),
+            Err(err) => assert_eq!(
+                "Body 'Result' is expected to be a string but got '0'",
+                err.to_string()
+            ),
+        }
+
+        // Header value that is not visible ASCII
+        let mut headers = http::HeaderMap::new();
+        headers.insert(
+            "Content-Type",
+            http::HeaderValue::from_bytes(b"application/json\xff").unwrap(),
+        );
+
+        let response = HttpResponse {
+            status: StatusCode::OK,
+            headers,
+            body: Some(serde_json::from_str(r#"{"Result": "0"}"#).unwrap()),
+            request_start: std::time::Instant::now(),
+            retry_count: 0,
+            raw_body: Bytes::new(),
+        };
+
+        match scenario.check_response(&response) {
+            Ok(_) => panic!("Expected error"),
+            Err(err) => assert_eq!("Header 'Content-Type' is not valid text", err.to_string()),
+        }
     }
 
     #[test]
@@ -705,6 +1287,7 @@ mod tests {
                 uri_var_name: vec![],
                 method: Method::GET,
                 headers: None,
+                headers_var_name: vec![],
                 body: None,
                 body_var_name: vec![],
                 timeout: Duration::from_secs(3),
@@ -716,11 +1299,15 @@ mod tests {
                     name: "Foo.Bar".into(),
                     value: BodyValueAssert::EqualString("Baz".into()),
                 }]),
+                body_regex: None,
+                body_length: None,
             },
             response_defines: vec![],
-            assert_panic: false,
             pre_script: None,
             post_script: None,
+            repeat: None,
+            delay: None,
+            define_regexes: HashMap::new(),
         };
 
         // Test Missing Field 'Foo'
@@ -735,6 +1322,7 @@ mod tests {
             body: Some(body),
             request_start: std::time::Instant::now(),
             retry_count: 0,
+            raw_body: Bytes::new(),
         };
 
         match scenario.check_response(&response) {
@@ -759,6 +1347,7 @@ mod tests {
             body: Some(body),
             request_start: std::time::Instant::now(),
             retry_count: 0,
+            raw_body: Bytes::new(),
         };
 
         match scenario.check_response(&response) {
@@ -788,6 +1377,7 @@ mod tests {
                 uri_var_name: vec![],
                 method: Method::GET,
                 headers: None,
+                headers_var_name: vec![],
                 body: None,
                 body_var_name: vec![],
                 timeout: Duration::from_secs(3),
@@ -796,11 +1386,15 @@ mod tests {
                 status: StatusCode::OK,
                 headers: None,
                 body: None,
+                body_regex: None,
+                body_length: None,
             },
             response_defines,
-            assert_panic: false,
             pre_script: None,
             post_script: None,
+            repeat: None,
+            delay: None,
+            define_regexes: HashMap::new(),
         };
 
         let mut ctx = ScriptContext::new(global);
@@ -816,6 +1410,7 @@ mod tests {
                     ),
                     request_start: std::time::Instant::now(),
                     retry_count: 0,
+                    raw_body: Bytes::new(),
                 },
             )
             .unwrap();
@@ -823,5 +1418,312 @@ mod tests {
         let object_id = ctx.get_variable("ObjectId").unwrap();
 
         assert_eq!(object_id, Value::String("0-1-2-3".into()));
+
+        // Missing or unsupported values fail instead of panicking
+        let response = |body: serde_json::Value| HttpResponse {
+            status: StatusCode::OK,
+            headers: http::HeaderMap::new(),
+            body: Some(body),
+            request_start: std::time::Instant::now(),
+            retry_count: 0,
+            raw_body: Bytes::new(),
+        };
+        let err = scenario
+            .from_response(&mut ctx, &response(serde_json::json!({"Result": 0})))
+            .err()
+            .unwrap();
+        assert_eq!(
+            err.to_string(),
+            "Define 'ObjectId': '$.ObjectId' not found in body"
+        );
+        let err = scenario
+            .from_response(
+                &mut ctx,
+                &response(serde_json::json!({"ObjectId": {"id": 1}})),
+            )
+            .err()
+            .unwrap();
+        assert_eq!(
+            err.to_string(),
+            "Define 'ObjectId': '$.ObjectId' is {\"id\":1}, expected a number or a string"
+        );
+        scenario
+            .from_response(&mut ctx, &response(serde_json::json!({"ObjectId": 7})))
+            .unwrap();
+        assert_eq!(ctx.get_variable("ObjectId").unwrap(), Value::Int(7));
+    }
+
+    #[test]
+    fn test_scenario_repeat() {
+        let repeat: Repeat = serde_yaml::from_str("count: 5").unwrap();
+        assert_eq!(repeat.iterations(), 5);
+        assert_eq!(repeat.expected_requests(), 5.0);
+        assert!(repeat.validate("update").is_ok());
+
+        let repeat: Repeat = serde_yaml::from_str("count: { min: 2, max: 4 }").unwrap();
+        for _ in 0..100 {
+            assert!((2..=4).contains(&repeat.iterations()));
+        }
+        assert_eq!(repeat.expected_requests(), 3.0);
+
+        let repeat: Repeat = serde_yaml::from_str("count: { min: 4, max: 2 }").unwrap();
+        assert!(repeat.validate("update").is_err());
+        let repeat: Repeat = serde_yaml::from_str("{}").unwrap();
+        assert!(repeat.validate("update").is_err());
+    }
+
+    #[test]
+    fn test_scenario_repeat_until() {
+        let global = Arc::new(RwLock::new(Global {
+            variables: HashMap::new(),
+        }));
+        let repeat: Repeat = serde_yaml::from_str(
+            r#"
+            until:
+              name: remaining
+              value:
+                type: Equal
+                value: 0
+            "#,
+        )
+        .unwrap();
+        assert!(repeat.validate("update").is_ok());
+        assert_eq!(repeat.iterations(), DEFAULT_MAX_REPEAT);
+        assert_eq!(repeat.expected_requests(), 1.0);
+
+        let mut ctx = ScriptContext::new(global);
+        assert!(!repeat.is_done(&ctx));
+        ctx.set_variable("remaining", Value::Int(2));
+        assert!(!repeat.is_done(&ctx));
+        ctx.set_variable("remaining", Value::String("0".into()));
+        assert!(repeat.is_done(&ctx));
+
+        let not_null = VariableAssert {
+            name: "remaining".into(),
+            value: VariableValueAssert::NotNull,
+        };
+        assert!(not_null.check(&ctx));
+        let not_equal = VariableAssert {
+            name: "state".into(),
+            value: VariableValueAssert::NotEqual(Value::String("OPEN".into())),
+        };
+        assert!(not_equal.check(&ctx));
+    }
+
+    #[test]
+    fn test_scenario_next_condition() {
+        let global = Arc::new(RwLock::new(Global {
+            variables: HashMap::new(),
+        }));
+        let mut ctx = ScriptContext::new(global);
+
+        let rule: NextRule = serde_yaml::from_str(
+            r#"
+            when:
+              status: 404
+            goto: cleanup
+            "#,
+        )
+        .unwrap();
+        assert_eq!(rule.goto.as_deref(), Some("cleanup"));
+        assert!(!rule.end);
+        assert!(rule.when.matches(StatusCode::NOT_FOUND, false, &ctx));
+        assert!(!rule.when.matches(StatusCode::OK, false, &ctx));
+
+        let rule: NextRule = serde_yaml::from_str(
+            r#"
+            when:
+              assert: passed
+              variable:
+                name: state
+                value:
+                  type: Equal
+                  value: CLOSED
+            end: true
+            "#,
+        )
+        .unwrap();
+        assert!(rule.end);
+        assert!(!rule.when.matches(StatusCode::OK, true, &ctx));
+        ctx.set_variable("state", Value::String("CLOSED".into()));
+        assert!(rule.when.matches(StatusCode::OK, true, &ctx));
+        assert!(!rule.when.matches(StatusCode::OK, false, &ctx));
+
+        // No condition, always matches
+        assert!(NextCondition::default().matches(StatusCode::CREATED, false, &ctx));
+        assert!(!NextCondition::default().matches_error(ErrorKind::Timeout, &ctx));
+
+        let rule: NextRule = serde_yaml::from_str(
+            r#"
+            when:
+              error: timeout
+            goto: cleanup
+            "#,
+        )
+        .unwrap();
+        assert!(rule.when.matches_error(ErrorKind::Timeout, &ctx));
+        assert!(!rule.when.matches_error(ErrorKind::Reset, &ctx));
+        assert!(!rule.when.matches(StatusCode::OK, true, &ctx));
+    }
+
+    #[test]
+    fn test_scenario_request_bodies() {
+        let global = Arc::new(RwLock::new(Global {
+            variables: HashMap::new(),
+        }));
+        let mut ctx = ScriptContext::new(global);
+        ctx.set_variable("imsi", Value::String("001010000000001".into()));
+        let file = std::env::temp_dir().join("h2load_test_body.bin");
+        std::fs::write(&file, [0u8, 159, 146, 150]).unwrap();
+
+        let scenario = |request: &str| -> Result<Scenario, Box<dyn Error>> {
+            let config: config::Scenario = serde_yaml::from_str(&format!(
+                "name: test\nrequest:\n  method: POST\n  path: /\n  timeout: 1s\n{}\nresponse:\n  assert:\n    status: 200\n",
+                request
+            ))
+            .unwrap();
+            Scenario::new(&config, "http://localhost:8080")
+        };
+        let content_type = |request: &HttpRequest| {
+            request
+                .headers
+                .iter()
+                .flatten()
+                .flat_map(|h| h.iter())
+                .find(|(name, _)| name.eq_ignore_ascii_case("content-type"))
+                .map(|(_, value)| value.clone())
+        };
+
+        let request = scenario("  body_type: text\n  body: \"imsi=${imsi} \"")
+            .unwrap()
+            .new_request(&ctx)
+            .unwrap();
+        assert_eq!(request.body.unwrap(), "imsi=001010000000001 ");
+
+        let request = scenario(
+            "  form:\n  - name: subscriber id\n    value: imsi-${imsi}\n  - name: q\n    value: a&b=c/é",
+        )
+        .unwrap()
+        .new_request(&ctx)
+        .unwrap();
+        assert_eq!(
+            content_type(&request).as_deref(),
+            Some("application/x-www-form-urlencoded")
+        );
+        assert_eq!(
+            request.body.unwrap(),
+            "subscriber+id=imsi-001010000000001&q=a%26b%3Dc%2F%C3%A9"
+        );
+
+        let request = scenario(&format!("  body_file: {}", file.display()))
+            .unwrap()
+            .new_request(&ctx)
+            .unwrap();
+        assert_eq!(request.body.unwrap(), &[0u8, 159, 146, 150][..]);
+
+        let request = scenario(&format!(
+            "  headers:\n  - Content-Type: application/json\n  multipart:\n  - content_type: application/json\n    body: '{{\"imsi\": \"${{imsi}}\"}}'\n  - content_type: application/vnd.3gpp.ngap\n    content_id: n2msg\n    file: {}",
+            file.display()
+        ))
+        .unwrap()
+        .new_request(&ctx)
+        .unwrap();
+        assert_eq!(
+            content_type(&request).unwrap(),
+            format!(
+                "multipart/related; boundary={}; type=\"application/json\"",
+                MULTIPART_BOUNDARY
+            )
+        );
+        let mut expected = format!(
+            "--{b}\r\nContent-Type: application/json\r\n\r\n{{\"imsi\": \"001010000000001\"}}\r\n--{b}\r\nContent-Type: application/vnd.3gpp.ngap\r\nContent-Id: n2msg\r\n\r\n",
+            b = MULTIPART_BOUNDARY
+        )
+        .into_bytes();
+        expected.extend_from_slice(&[0, 159, 146, 150]);
+        expected.extend_from_slice(format!("\r\n--{}--\r\n", MULTIPART_BOUNDARY).as_bytes());
+        assert_eq!(request.body.unwrap(), expected);
+
+        assert!(scenario("  body: '{}'\n  form: []").is_err());
+        assert!(scenario("  multipart: []").is_err());
+        assert!(scenario("  multipart:\n  - content_type: text/plain").is_err());
+        assert!(scenario("  body_file: ./does-not-exist.bin").is_err());
+        std::fs::remove_file(file).unwrap();
+    }
+
+    #[test]
+    fn test_scenario_raw_body() {
+        let config: config::Scenario = serde_yaml::from_str(
+            r#"
+            name: test
+            request:
+              method: GET
+              path: /
+              timeout: 1s
+            response:
+              assert:
+                status: 200
+                body_regex: "^<result>[0-9]+</result>"
+                body_length: { min: 19, max: 100 }
+              define:
+                - name: result
+                  from: Regex
+                  path: "<result>([0-9]+)</result>"
+            "#,
+        )
+        .unwrap();
+        let scenario = Scenario::new(&config, "http://localhost:8080").unwrap();
+        let response = |body: &'static str| HttpResponse {
+            status: StatusCode::OK,
+            headers: http::HeaderMap::new(),
+            body: None,
+            request_start: std::time::Instant::now(),
+            retry_count: 0,
+            raw_body: Bytes::from_static(body.as_bytes()),
+        };
+
+        assert!(scenario
+            .check_response(&response("<result>42</result>"))
+            .is_ok());
+        assert_eq!(
+            scenario
+                .check_response(&response("<error>42</error>"))
+                .err()
+                .unwrap()
+                .to_string(),
+            "Body is expected to match '^<result>[0-9]+</result>'"
+        );
+        assert_eq!(
+            scenario
+                .check_response(&response("<result>1"))
+                .err()
+                .unwrap()
+                .to_string(),
+            "Body is expected to match '^<result>[0-9]+</result>'"
+        );
+        assert_eq!(
+            scenario
+                .check_response(&response("<result>1</result>"))
+                .err()
+                .unwrap()
+                .to_string(),
+            "Body length is expected to be between 19 and 100 but got 18"
+        );
+
+        let global = Arc::new(RwLock::new(Global {
+            variables: HashMap::new(),
+        }));
+        let mut ctx = ScriptContext::new(global);
+        scenario
+            .from_response(&mut ctx, &response("<result>42</result>"))
+            .unwrap();
+        assert_eq!(
+            ctx.get_variable("result").unwrap(),
+            Value::String("42".into())
+        );
+
+        let mut config = config.clone();
+        config.response.assert.body_regex = Some("(".into());
+        assert!(Scenario::new(&config, "http://localhost:8080").is_err());
     }
 }
@@ -85,8 +85,7 @@ impl Script {
             for arg in config_args {
                 if arg.is_string() {
                     let str_arg = arg.as_string();
-                    if str_arg.starts_with("$") {
-                        let var_name = &str_arg[1..];
+                    if let Some(var_name) = str_arg.strip_prefix('$') {
                         args.push(ScriptVariable::Variable(var_name.into()));
                         continue;
                     }
@@ -120,7 +119,7 @@ impl Script {
                     let arg0 = arg0.as_string();
                     let value = f.apply(Some(arg0));
                     Value::String(value)
-                } else if self.args.len() == 0 {
+                } else if self.args.is_empty() {
                     let value = f.apply(None);
                     Value::String(value)
                 } else {
@@ -128,7 +127,7 @@ impl Script {
                 }
             }
             function::Function::Random(f) => {
-                if self.args.len() == 0 {
+                if self.args.is_empty() {
                     let value = f.apply();
                     Value::Int(value)
                 } else {
@@ -148,8 +147,7 @@ impl Script {
             function::Function::Copy(f) => {
                 if self.args.len() == 1 {
                     let arg0 = self.args[0].get_value(ctx)?;
-                    let value = f.apply(&arg0);
-                    value
+                    f.apply(&arg0)
                 } else {
                     return Err(Error::ScriptError("Expects 1 argument".into()));
                 }
@@ -189,7 +187,7 @@ mod tests {
         let value = result.as_string();
 
         let today = chrono::Utc::now().format("%Y-%m-%d").to_string();
-        assert!(value.len() > 0);
+        assert!(!value.is_empty());
         assert!(value.starts_with(&today));
     }
 
@@ -213,7 +211,7 @@ mod tests {
 
         let result = ctx.get_variable("value").unwrap();
         let value = result.as_int();
-        assert!(value >= 1 && value <= 10);
+        assert!((1..=10).contains(&value));
     }
 
     // let var1 = var2
@@ -48,7 +48,7 @@ impl Scripting {
         let lines: Vec<&str> = self.raw.split('\n').collect();
         for line in lines {
             let parts: Vec<&str> = line.split(' ').collect();
-            if parts.len() > 0 {
+            if !parts.is_empty() {
                 let def = parts[0];
                 if def == "def" {
                     // Validate
@@ -1,11 +1,32 @@
+use hdrhistogram::Histogram;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::sync::atomic::AtomicBool;
 use std::sync::atomic::AtomicU32;
 use std::sync::atomic::AtomicU64;
+use std::sync::Arc;
+use std::sync::Mutex;
 
 pub struct ApiStats {
     success_counter: AtomicU32,
     error_counter: AtomicU32,
-    total_rtt: AtomicU64,
+    rtt_histogram: Mutex<Histogram<u64>>,
+    // From the time the request was scheduled, corrected for coordinated omission
+    response_time_histogram: Mutex<Histogram<u64>>,
+    window_rtt_histogram: Mutex<Histogram<u64>>,
+    in_flight_counter: AtomicU32,
     total_retry: AtomicU32,
+    reconnect_counter: AtomicU32,
+    ping_counter: AtomicU32,
+    ping_timeout_counter: AtomicU32,
+    total_ping_rtt: AtomicU64,
+    // Sends held back, by max_in_flight or the server's MAX_CONCURRENT_STREAMS
+    delayed_counter: AtomicU32,
+    // Sends given up on because max_in_flight was reached
+    dropped_counter: AtomicU32,
+    // Closed flag of each connection in the runner's pool
+    connections: Mutex<Vec<Arc<AtomicBool>>>,
+    scenarios: Mutex<Vec<ScenarioStats>>,
 }
 
 unsafe impl Sync for ApiStats {}
@@ -16,8 +37,19 @@ impl ApiStats {
         ApiStats {
             success_counter: AtomicU32::new(0),
             error_counter: AtomicU32::new(0),
-            total_rtt: AtomicU64::new(0),
+            rtt_histogram: Mutex::new(new_histogram()),
+            response_time_histogram: Mutex::new(new_histogram()),
+            window_rtt_histogram: Mutex::new(new_histogram()),
+            in_flight_counter: AtomicU32::new(0),
             total_retry: AtomicU32::new(0),
+            reconnect_counter: AtomicU32::new(0),
+            ping_counter: AtomicU32::new(0),
+            ping_timeout_counter: AtomicU32::new(0),
+            total_ping_rtt: AtomicU64::new(0),
+            delayed_counter: AtomicU32::new(0),
+            dropped_counter: AtomicU32::new(0),
+            connections: Mutex::new(vec![]),
+            scenarios: Mutex::new(vec![]),
         }
     }
 
@@ -42,12 +74,49 @@ impl ApiStats {
     }
 
     pub fn inc_rtt(&self, rtt: u64) {
-        self.total_rtt
-            .fetch_add(rtt, std::sync::atomic::Ordering::Relaxed);
+        self.rtt_histogram.lock().unwrap().saturating_record(rtt);
+        self.window_rtt_histogram
+            .lock()
+            .unwrap()
+            .saturating_record(rtt);
+    }
+
+    pub fn get_rtt(&self) -> Histogram<u64> {
+        self.rtt_histogram.lock().unwrap().clone()
+    }
+
+    pub fn inc_response_time(&self, response_time: u64) {
+        self.response_time_histogram
+            .lock()
+            .unwrap()
+            .saturating_record(response_time);
+    }
+
+    pub fn get_response_time(&self) -> Histogram<u64> {
+        self.response_time_histogram.lock().unwrap().clone()
     }
 
-    pub fn get_rtt(&self) -> u64 {
-        self.total_rtt.load(std::sync::atomic::Ordering::Relaxed)
+    // Round trips recorded since the previous call
+    pub fn take_window_rtt(&self) -> Histogram<u64> {
+        std::mem::replace(
+            &mut *self.window_rtt_histogram.lock().unwrap(),
+            new_histogram(),
+        )
+    }
+
+    pub fn inc_in_flight(&self) {
+        self.in_flight_counter
+            .fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+    }
+
+    pub fn dec_in_flight(&self) {
+        self.in_flight_counter
+            .fetch_sub(1, std::sync::atomic::Ordering::Relaxed);
+    }
+
+    pub fn get_in_flight(&self) -> u32 {
+        self.in_flight_counter
+            .load(std::sync::atomic::Ordering::Relaxed)
     }
 
     pub fn inc_retry(&self, retry: u32) {
@@ -58,4 +127,318 @@ impl ApiStats {
     pub fn get_retry(&self) -> u32 {
         self.total_retry.load(std::sync::atomic::Ordering::Relaxed)
     }
+
+    pub fn inc_reconnect(&self) {
+        self.reconnect_counter
+            .fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+    }
+
+    pub fn get_reconnect(&self) -> u32 {
+        self.reconnect_counter
+            .load(std::sync::atomic::Ordering::Relaxed)
+    }
+
+    pub fn inc_ping(&self, rtt: u64) {
+        self.ping_counter
+            .fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+        self.total_ping_rtt
+            .fetch_add(rtt, std::sync::atomic::Ordering::Relaxed);
+    }
+
+    pub fn get_ping(&self) -> u32 {
+        self.ping_counter.load(std::sync::atomic::Ordering::Relaxed)
+    }
+
+    pub fn get_ping_rtt(&self) -> u64 {
+        self.total_ping_rtt
+            .load(std::sync::atomic::Ordering::Relaxed)
+    }
+
+    pub fn inc_ping_timeout(&self) {
+        self.ping_timeout_counter
+            .fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+    }
+
+    pub fn get_ping_timeout(&self) -> u32 {
+        self.ping_timeout_counter
+            .load(std::sync::atomic::Ordering::Relaxed)
+    }
+
+    pub fn inc_delayed(&self) {
+        self.delayed_counter
+            .fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+    }
+
+    pub fn get_delayed(&self) -> u32 {
+        self.delayed_counter
+            .load(std::sync::atomic::Ordering::Relaxed)
+    }
+
+    pub fn inc_dropped(&self) {
+        self.dropped_counter
+            .fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+    }
+
+    pub fn get_dropped(&self) -> u32 {
+        self.dropped_counter
+            .load(std::sync::atomic::Ordering::Relaxed)
+    }
+
+    pub fn set_connections(&self, connections: Vec<Arc<AtomicBool>>) {
+        *self.connections.lock().unwrap() = connections;
+    }
+
+    pub fn get_open_connections(&self) -> u32 {
+        self.connections
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|closed| !closed.load(std::sync::atomic::Ordering::Relaxed))
+            .count() as u32
+    }
+
+    pub fn set_scenarios(&self, names: &[&str]) {
+        *self.scenarios.lock().unwrap() =
+            names.iter().map(|name| ScenarioStats::new(name)).collect();
+    }
+
+    pub fn update_scenario(&self, scenario_id: usize, update: impl FnOnce(&mut ScenarioStats)) {
+        update(&mut self.scenarios.lock().unwrap()[scenario_id]);
+    }
+
+    pub fn get_scenarios(&self) -> Vec<ScenarioStats> {
+        self.scenarios.lock().unwrap().clone()
+    }
+}
+
+#[derive(Clone)]
+pub struct ScenarioStats {
+    pub name: String,
+    pub success: u32,
+    pub error: u32,
+    pub timeout: u32,
+    // Failed without a response, besides timeouts
+    pub reset: u32,
+    pub connection_closed: u32,
+    pub protocol_error: u32,
+    pub retry: u32,
+    pub rtt_histogram: Histogram<u64>,
+    pub response_time_histogram: Histogram<u64>,
+    pub status_codes: BTreeMap<u16, u32>,
+    // Failed requests by reason, see MAX_FAILURE_REASONS
+    pub failures: BTreeMap<String, u32>,
+}
+
+// Reasons may hold values of the response, past this many distinct ones they
+// are counted as "other"
+const MAX_FAILURE_REASONS: usize = 100;
+
+impl ScenarioStats {
+    pub fn new(name: &str) -> ScenarioStats {
+        ScenarioStats {
+            name: name.into(),
+            success: 0,
+            error: 0,
+            timeout: 0,
+            reset: 0,
+            connection_closed: 0,
+            protocol_error: 0,
+            retry: 0,
+            rtt_histogram: new_histogram(),
+            response_time_histogram: new_histogram(),
+            status_codes: BTreeMap::new(),
+            failures: BTreeMap::new(),
+        }
+    }
+
+    pub fn inc_status(&mut self, status: u16) {
+        *self.status_codes.entry(status).or_insert(0) += 1;
+    }
+
+    pub fn inc_failure(&mut self, reason: &str) {
+        self.add_failure(reason, 1);
+    }
+
+    fn add_failure(&mut self, reason: &str, count: u32) {
+        let reason =
+            if self.failures.contains_key(reason) || self.failures.len() < MAX_FAILURE_REASONS {
+                reason
+            } else {
+                "other"
+            };
+        *self.failures.entry(reason.into()).or_insert(0) += count;
+    }
+
+    // Most frequent failure reasons first
+    pub fn top_failures(&self, n: usize) -> Vec<(&str, u32)> {
+        let mut failures: Vec<(&str, u32)> = self
+            .failures
+            .iter()
+            .map(|(reason, count)| (reason.as_str(), *count))
+            .collect();
+        failures.sort_by_key(|(_, count)| std::cmp::Reverse(*count));
+        failures.truncate(n);
+        failures
+    }
+
+    pub fn add(&mut self, other: &ScenarioStats) {
+        self.success += other.success;
+        self.error += other.error;
+        self.timeout += other.timeout;
+        self.reset += other.reset;
+        self.connection_closed += other.connection_closed;
+        self.protocol_error += other.protocol_error;
+        self.retry += other.retry;
+        self.rtt_histogram.add(&other.rtt_histogram).unwrap();
+        self.response_time_histogram
+            .add(&other.response_time_histogram)
+            .unwrap();
+        for (status, count) in &other.status_codes {
+            *self.status_codes.entry(*status).or_insert(0) += count;
+        }
+        for (reason, count) in &other.failures {
+            self.add_failure(reason, *count);
+        }
+    }
+}
+
+// Merge per-scenario stats by name, keeping the order scenarios were first seen
+pub fn merge_scenario_stats(merged: &mut Vec<ScenarioStats>, stats: &[ScenarioStats]) {
+    for scenario in stats {
+        match merged.iter_mut().find(|s| s.name == scenario.name) {
+            Some(existing) => existing.add(scenario),
+            None => {
+                let mut new = ScenarioStats::new(&scenario.name);
+                new.add(scenario);
+                merged.push(new);
+            }
+        }
+    }
+}
+
+// Latency histogram from 1us up to 1h, 3 significant digits
+pub fn new_histogram() -> Histogram<u64> {
+    Histogram::new_with_bounds(1, 3_600_000_000, 3).unwrap()
+}
+
+#[derive(Serialize)]
+pub struct LatencySummary {
+    pub mean: f64,
+    pub p50: f64,
+    pub p90: f64,
+    pub p95: f64,
+    pub p99: f64,
+    pub p999: f64,
+    pub max: f64,
+}
+
+impl LatencySummary {
+    // Summary in milliseconds of a histogram recorded in microseconds
+    pub fn new(histogram: &Histogram<u64>) -> LatencySummary {
+        let ms = |us: u64| us as f64 / 1000.0;
+        LatencySummary {
+            mean: histogram.mean() / 1000.0,
+            p50: ms(histogram.value_at_quantile(0.5)),
+            p90: ms(histogram.value_at_quantile(0.9)),
+            p95: ms(histogram.value_at_quantile(0.95)),
+            p99: ms(histogram.value_at_quantile(0.99)),
+            p999: ms(histogram.value_at_quantile(0.999)),
+            max: ms(histogram.max()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_latency_summary() {
+        let stats1 = ApiStats::new();
+        let stats2 = ApiStats::new();
+        for rtt in 1..=500 {
+            stats1.inc_rtt(rtt * 1000);
+        }
+        for rtt in 501..=1000 {
+            stats2.inc_rtt(rtt * 1000);
+        }
+
+        // Merged across runners
+        let mut histogram = stats1.get_rtt();
+        histogram.add(stats2.get_rtt()).unwrap();
+        assert_eq!(histogram.len(), 1000);
+
+        let summary = LatencySummary::new(&histogram);
+        assert!((summary.mean - 500.5).abs() < 1.0);
+        assert!((summary.p50 - 500.0).abs() < 1.0);
+        assert!((summary.p90 - 900.0).abs() < 1.0);
+        assert!((summary.p99 - 990.0).abs() < 1.0);
+        assert!((summary.p999 - 999.0).abs() < 1.0);
+        assert!((summary.max - 1000.0).abs() < 1.0);
+    }
+
+    #[test]
+    fn test_merge_scenario_stats() {
+        let mut create = ScenarioStats::new("create");
+        create.success = 2;
+        create.rtt_histogram.record(1000).unwrap();
+        create.rtt_histogram.record(3000).unwrap();
+        create.inc_status(201);
+        create.inc_status(201);
+        let mut update = ScenarioStats::new("update");
+        update.error = 2;
+        update.timeout = 1;
+        update.reset = 1;
+
+        let mut merged = vec![];
+        merge_scenario_stats(&mut merged, &[create, update]);
+
+        let mut create = ScenarioStats::new("create");
+        create.success = 1;
+        create.retry = 2;
+        create.rtt_histogram.record(2000).unwrap();
+        create.response_time_histogram.record(5000).unwrap();
+        create.inc_status(201);
+        create.inc_status(503);
+        let release = ScenarioStats::new("release");
+        merge_scenario_stats(&mut merged, &[create, release]);
+
+        let names: Vec<&str> = merged.iter().map(|s| s.name.as_str()).collect();
+        assert_eq!(names, vec!["create", "update", "release"]);
+        assert_eq!(merged[0].success, 3);
+        assert_eq!(merged[0].retry, 2);
+        assert_eq!(merged[0].rtt_histogram.len(), 3);
+        assert_eq!(merged[0].response_time_histogram.len(), 1);
+        assert_eq!(merged[0].status_codes, BTreeMap::from([(201, 3), (503, 1)]));
+        assert!((LatencySummary::new(&merged[0].rtt_histogram).mean - 2.0).abs() < 0.01);
+        assert_eq!(merged[1].error, 2);
+        assert_eq!(merged[1].timeout, 1);
+        assert_eq!(merged[1].reset, 1);
+        assert_eq!(merged[2].success + merged[2].error, 0);
+    }
+
+    #[test]
+    fn test_scenario_stats_failures() {
+        let mut create = ScenarioStats::new("create");
+        create.inc_failure("Expected status code: 201, got: 404");
+        create.inc_failure("Expected status code: 201, got: 500");
+        create.inc_failure("Expected status code: 201, got: 500");
+
+        let mut other = ScenarioStats::new("create");
+        other.inc_failure("Expected status code: 201, got: 404");
+        other.inc_failure("Expected status code: 201, got: 404");
+        create.add(&other);
+        assert_eq!(
+            create.top_failures(1),
+            vec![("Expected status code: 201, got: 404", 3)]
+        );
+
+        // Distinct reasons are capped
+        let mut update = ScenarioStats::new("update");
+        for i in 0..MAX_FAILURE_REASONS + 10 {
+            update.inc_failure(&format!("Body 'id' is expected to be '0' but got '{}'", i));
+        }
+        assert_eq!(update.failures.len(), MAX_FAILURE_REASONS + 1);
+        assert_eq!(update.top_failures(1), vec![("other", 10)]);
+    }
 }
@@ -0,0 +1,160 @@
+use crate::config::TlsConfig;
+use p12_keystore::KeyStore;
+use p12_keystore::Pkcs12ImportPolicy;
+use std::error::Error;
+use std::fs::File;
+use std::io::BufReader;
+use std::sync::Arc;
+use tokio_rustls::rustls;
+use tokio_rustls::rustls::client::danger::HandshakeSignatureValid;
+use tokio_rustls::rustls::client::danger::ServerCertVerified;
+use tokio_rustls::rustls::client::danger::ServerCertVerifier;
+use tokio_rustls::rustls::crypto::CryptoProvider;
+use tokio_rustls::rustls::pki_types::CertificateDer;
+use tokio_rustls::rustls::pki_types::PrivateKeyDer;
+use tokio_rustls::rustls::pki_types::PrivatePkcs8KeyDer;
+use tokio_rustls::rustls::pki_types::ServerName;
+use tokio_rustls::rustls::pki_types::UnixTime;
+use tokio_rustls::rustls::ClientConfig;
+use tokio_rustls::rustls::DigitallySignedStruct;
+use tokio_rustls::rustls::RootCertStore;
+use tokio_rustls::rustls::SignatureScheme;
+
+pub const ALPN_H2: &[u8] = b"h2";
+
+type ClientIdentity = (Vec<CertificateDer<'static>>, PrivateKeyDer<'static>);
+
+pub fn client_config(config: &TlsConfig) -> Result<ClientConfig, Box<dyn Error>> {
+    let provider = Arc::new(rustls::crypto::ring::default_provider());
+    let builder = ClientConfig::builder_with_provider(Arc::clone(&provider))
+        .with_safe_default_protocol_versions()?;
+
+    let builder = if config.insecure_skip_verify {
+        log::warn!("TLS server certificate verification is disabled");
+        builder
+            .dangerous()
+            .with_custom_certificate_verifier(Arc::new(NoVerifier(provider)))
+    } else {
+        let mut roots = RootCertStore::empty();
+        roots.extend(webpki_roots::TLS_SERVER_ROOTS.iter().cloned());
+        if let Some(ca_cert) = &config.ca_cert {
+            for cert in load_certs(ca_cert)? {
+                roots.add(cert)?;
+            }
+        }
+        builder.with_root_certificates(roots)
+    };
+
+    let mut client_config = match client_identity(config)? {
+        Some((certs, key)) => builder.with_client_auth_cert(certs, key)?,
+        None => builder.with_no_client_auth(),
+    };
+
+    // Only speak HTTP/2, the server must select it during the handshake
+    client_config.alpn_protocols = vec![ALPN_H2.to_vec()];
+
+    Ok(client_config)
+}
+
+// Client certificate chain and key for mutual TLS, either from PEM files or a PKCS#12 bundle
+fn client_identity(config: &TlsConfig) -> Result<Option<ClientIdentity>, Box<dyn Error>> {
+    match (
+        &config.client_cert,
+        &config.client_key,
+        &config.client_pkcs12,
+    ) {
+        (Some(_), _, Some(_)) | (_, Some(_), Some(_)) => {
+            Err("client_pkcs12 and client_cert/client_key are mutually exclusive".into())
+        }
+        (Some(cert), Some(key), None) => Ok(Some((load_certs(cert)?, load_private_key(key)?))),
+        (None, None, Some(pkcs12)) => {
+            let password = config.client_pkcs12_password.as_deref().unwrap_or_default();
+            Ok(Some(load_pkcs12(pkcs12, password)?))
+        }
+        (None, None, None) => Ok(None),
+        _ => Err("Both client_cert and client_key must be set".into()),
+    }
+}
+
+pub fn load_certs(path: &str) -> Result<Vec<CertificateDer<'static>>, Box<dyn Error>> {
+    let file = File::open(path).map_err(|e| format!("Failed to open '{}': {}", path, e))?;
+    let certs = rustls_pemfile::certs(&mut BufReader::new(file)).collect::<Result<Vec<_>, _>>()?;
+    if certs.is_empty() {
+        return Err(format!("No certificate found in '{}'", path).into());
+    }
+    Ok(certs)
+}
+
+pub fn load_private_key(path: &str) -> Result<PrivateKeyDer<'static>, Box<dyn Error>> {
+    let file = File::open(path).map_err(|e| format!("Failed to open '{}': {}", path, e))?;
+    rustls_pemfile::private_key(&mut BufReader::new(file))?
+        .ok_or_else(|| format!("No private key found in '{}'", path).into())
+}
+
+pub fn load_pkcs12(path: &str, password: &str) -> Result<ClientIdentity, Box<dyn Error>> {
+    let data = std::fs::read(path).map_err(|e| format!("Failed to open '{}': {}", path, e))?;
+    let keystore = KeyStore::from_pkcs12(&data, password, Pkcs12ImportPolicy::Strict)
+        .map_err(|e| format!("Failed to parse PKCS#12 '{}': {:?}", path, e))?;
+
+    let (_, chain) = keystore
+        .private_key_chain()
+        .ok_or_else(|| format!("No private key found in '{}'", path))?;
+
+    let certs = chain
+        .certs()
+        .iter()
+        .map(|cert| CertificateDer::from(cert.as_der().to_vec()))
+        .collect();
+    let key = PrivateKeyDer::Pkcs8(PrivatePkcs8KeyDer::from(chain.key().as_der().to_vec()));
+
+    Ok((certs, key))
+}
+
+// Accepts any server certificate, only used when `insecure_skip_verify` is set
+#[derive(Debug)]
+struct NoVerifier(Arc<CryptoProvider>);
+
+impl ServerCertVerifier for NoVerifier {
+    fn verify_server_cert(
+        &self,
+        _end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        Ok(ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        rustls::crypto::verify_tls12_signature(
+            message,
+            cert,
+            dss,
+            &self.0.signature_verification_algorithms,
+        )
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        rustls::crypto::verify_tls13_signature(
+            message,
+            cert,
+            dss,
+            &self.0.signature_verification_algorithms,
+        )
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.0.signature_verification_algorithms.supported_schemes()
+    }
+}